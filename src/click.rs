@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::{mpsc, watch};
+
+use crate::status::Segment;
+use crate::util::process;
+
+/// Mouse buttons, deserialized either from the numbers swaybar sends in
+/// click events or from names in the config file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "ButtonRepr")]
+pub enum Button {
+    Left,
+    Middle,
+    Right,
+    ScrollUp,
+    ScrollDown,
+    ScrollLeft,
+    ScrollRight,
+}
+
+impl TryFrom<u32> for Button {
+    type Error = String;
+
+    fn try_from(button: u32) -> Result<Self, Self::Error> {
+        match button {
+            1 => Ok(Button::Left),
+            2 => Ok(Button::Middle),
+            3 => Ok(Button::Right),
+            4 => Ok(Button::ScrollUp),
+            5 => Ok(Button::ScrollDown),
+            6 => Ok(Button::ScrollLeft),
+            7 => Ok(Button::ScrollRight),
+            _ => Err(format!("unknown button: {}", button)),
+        }
+    }
+}
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(button: &str) -> Result<Self, Self::Err> {
+        match button {
+            "left" => Ok(Button::Left),
+            "middle" => Ok(Button::Middle),
+            "right" => Ok(Button::Right),
+            "scroll_up" => Ok(Button::ScrollUp),
+            "scroll_down" => Ok(Button::ScrollDown),
+            "scroll_left" => Ok(Button::ScrollLeft),
+            "scroll_right" => Ok(Button::ScrollRight),
+            _ => Err(format!("unknown button: {}", button)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ButtonRepr {
+    Number(u32),
+    Name(String),
+}
+
+impl TryFrom<ButtonRepr> for Button {
+    type Error = String;
+
+    fn try_from(repr: ButtonRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ButtonRepr::Number(button) => button.try_into(),
+            ButtonRepr::Name(button) => button.parse(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Action {
+    /// Run a shell command, detached from the bar's stdio
+    Spawn(String),
+    /// Send a message to the segment's source, see `Segment::messages`
+    Message(String),
+}
+
+/// A click event as sent by swaybar on stdin, see swaybar-protocol(7)
+#[derive(Debug, Deserialize)]
+pub struct Event {
+    pub name: Option<String>,
+    pub instance: Option<String>,
+    pub button: Button,
+}
+
+struct Target {
+    name: String,
+    instance: Option<String>,
+    actions: HashMap<Button, Action>,
+    messages: Option<mpsc::UnboundedSender<String>>,
+}
+
+/// Routes click events to the actions of the segment that was clicked
+#[derive(Default)]
+pub struct Handler {
+    targets: Vec<Target>,
+}
+
+impl Handler {
+    pub fn register(&mut self, segment: &Segment) {
+        self.targets.push(Target {
+            name: segment.name.clone(),
+            instance: segment.instance.clone(),
+            actions: segment.actions().clone(),
+            messages: segment.message_sender(),
+        });
+    }
+
+    pub fn handle(&self, event: &Event) {
+        let target = self.targets.iter().find(|target|
+            event.name.as_ref() == Some(&target.name) && event.instance == target.instance);
+
+        let Some(target) = target else { return };
+
+        match target.actions.get(&event.button) {
+            Some(Action::Spawn(command)) => process::spawn(command),
+            Some(Action::Message(message)) => {
+                let sent = target.messages.as_ref()
+                    .map(|tx| tx.send(message.clone()).is_ok())
+                    .unwrap_or(false);
+
+                if !sent {
+                    eprintln!("click: segment {} does not accept messages", target.name);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// Reads click events from swaybar until the input is closed. The handler
+/// is replaced when the config is reloaded.
+pub async fn run(handler: watch::Receiver<Handler>, input: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(input).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        match parse_event(&line) {
+            Ok(Some(event)) => handler.borrow().handle(&event),
+            Ok(None) => {}
+            Err(e) => eprintln!("click: could not parse event {:?}: {}", line, e),
+        }
+    }
+}
+
+fn parse_event(line: &str) -> Result<Option<Event>, serde_json::Error> {
+    // click events are elements of an infinite json array, so each line
+    // is either the opening bracket or an object with a leading comma
+    let line = line.trim().trim_start_matches(['[', ',']).trim();
+
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    serde_json::from_str(line).map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert!(parse_event("[").unwrap().is_none());
+
+        let event = parse_event(r#"{"name":"network","button":1,"x":10,"y":4}"#).unwrap().unwrap();
+        assert_eq!(event.name.as_deref(), Some("network"));
+        assert_eq!(event.instance, None);
+        assert_eq!(event.button, Button::Left);
+
+        let event = parse_event(r#",{"name":"clock","instance":"utc","button":5}"#).unwrap().unwrap();
+        assert_eq!(event.instance.as_deref(), Some("utc"));
+        assert_eq!(event.button, Button::ScrollDown);
+    }
+
+    #[test]
+    fn message() {
+        let mut segment = Segment::new("clock", "")
+            .on_click(Button::Left, Action::Message("cycle".to_owned()));
+
+        let (tx, mut messages) = mpsc::unbounded_channel();
+        segment.set_messages(tx);
+
+        let mut handler = Handler::default();
+        handler.register(&segment);
+
+        handler.handle(&Event { name: Some("clock".to_owned()), instance: None, button: Button::Right });
+        handler.handle(&Event { name: Some("other".to_owned()), instance: None, button: Button::Left });
+        handler.handle(&Event { name: Some("clock".to_owned()), instance: None, button: Button::Left });
+
+        assert_eq!(messages.try_recv().unwrap(), "cycle");
+        assert!(messages.try_recv().is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::click::{Action, Button};
+use crate::control::Selector;
+use crate::flash;
+use crate::source;
+use crate::status::{LineBuilder, MinWidth, Segment};
+
+const DEFAULT: &str = include_str!("default.toml");
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Parse(Option<PathBuf>, toml::de::Error),
+    DuplicateSegment(String),
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, err) => write!(f, "could not read {}: {}", path.display(), err),
+            Error::Parse(Some(path), err) => write!(f, "invalid config in {}: {}", path.display(), err),
+            Error::Parse(None, err) => write!(f, "invalid built-in config: {}", err),
+            Error::DuplicateSegment(name) => write!(f, "more than one segment named {}, set `instance` to tell them apart", name),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(rename = "segment", default)]
+    pub segments: Vec<SegmentConfig>,
+
+    #[serde(default)]
+    pub flash: flash::Style,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "RawSegment")]
+pub struct SegmentConfig {
+    pub name: String,
+    pub instance: Option<String>,
+    pub icon: String,
+    pub color: Option<String>,
+    pub separator: Option<bool>,
+    pub min_width: Option<MinWidth>,
+    pub on_click: HashMap<Button, Action>,
+    pub source: source::Config,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SourceKind {
+    Battery,
+    Clock,
+    Networkmanager,
+    WpaSupplicant,
+}
+
+#[derive(Deserialize)]
+struct RawSegment {
+    source: SourceKind,
+    name: Option<String>,
+    instance: Option<String>,
+    #[serde(default)]
+    icon: String,
+    color: Option<String>,
+    separator: Option<bool>,
+    min_width: Option<MinWidth>,
+    #[serde(default)]
+    on_click: HashMap<Button, Action>,
+    // every other key is an option of the source:
+    #[serde(flatten)]
+    options: toml::value::Table,
+}
+
+impl SourceKind {
+    fn name(self) -> &'static str {
+        match self {
+            SourceKind::Battery => "battery",
+            SourceKind::Clock => "clock",
+            SourceKind::Networkmanager => "networkmanager",
+            SourceKind::WpaSupplicant => "wpa_supplicant",
+        }
+    }
+
+    fn config(self, options: toml::value::Table) -> Result<source::Config, toml::de::Error> {
+        let options = toml::Value::Table(options);
+
+        Ok(match self {
+            SourceKind::Battery => source::Config::Battery(options.try_into()?),
+            SourceKind::Clock => source::Config::Clock(options.try_into()?),
+            SourceKind::Networkmanager => source::Config::NetworkManager(options.try_into()?),
+            SourceKind::WpaSupplicant => source::Config::WpaSupplicant(options.try_into()?),
+        })
+    }
+}
+
+impl TryFrom<RawSegment> for SegmentConfig {
+    type Error = String;
+
+    fn try_from(raw: RawSegment) -> Result<Self, Self::Error> {
+        let kind = raw.source.name();
+
+        let source = raw.source.config(raw.options)
+            .map_err(|e| format!("{} source: {}", kind, e))?;
+
+        let sends_messages = raw.on_click.values()
+            .any(|action| matches!(action, Action::Message(_)));
+
+        if sends_messages && !source.accepts_messages() {
+            return Err(format!("{} source does not accept messages from on_click", kind));
+        }
+
+        Ok(SegmentConfig {
+            name: raw.name.unwrap_or_else(|| kind.to_owned()),
+            instance: raw.instance,
+            icon: raw.icon,
+            color: raw.color,
+            separator: raw.separator,
+            min_width: raw.min_width,
+            on_click: raw.on_click,
+            source,
+        })
+    }
+}
+
+impl Config {
+    /// Loads the config from `path`, or from the default location if no
+    /// path is given. Falls back to the built-in config if there is no
+    /// file at the default location.
+    pub fn load(path: Option<&Path>) -> Result<Config, Error> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Config::parse(DEFAULT, None),
+            },
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| Error::Io(path.clone(), e))?;
+
+        Config::parse(&contents, Some(path))
+    }
+
+    fn parse(contents: &str, path: Option<PathBuf>) -> Result<Config, Error> {
+        let config: Config = toml::from_str(contents)
+            .map_err(|e| Error::Parse(path, e))?;
+
+        let mut seen = HashSet::new();
+
+        for segment in &config.segments {
+            if !seen.insert((&segment.name, &segment.instance)) {
+                return Err(Error::DuplicateSegment(segment.name.clone()));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Builds the status line, reusing sources already running in `pool`
+    /// and stopping those no longer in the config.
+    pub fn line_builder(&self, pool: &mut source::Pool) -> LineBuilder {
+        let builder = self.segments.iter().fold(LineBuilder::new(), |builder, config| {
+            let mut segment = config.segment();
+            let (source, messages) = pool.get(&config.key());
+            segment.set_messages(messages);
+            builder.segment(segment, source)
+        });
+
+        pool.retain(self.segments.iter().map(SegmentConfig::key));
+
+        builder
+    }
+
+    /// Stops the sources of the selected segments, so that building the
+    /// line again starts them afresh
+    pub fn restart(&self, pool: &mut source::Pool, selector: &Selector) {
+        for segment in &self.segments {
+            if selector.matches(&segment.name, segment.instance.as_deref()) {
+                pool.stop(&segment.key());
+            }
+        }
+    }
+
+    pub fn selectors(&self) -> Vec<Selector> {
+        self.segments.iter()
+            .map(|segment| Selector { name: segment.name.clone(), instance: segment.instance.clone() })
+            .collect()
+    }
+}
+
+impl SegmentConfig {
+    fn key(&self) -> source::Key {
+        source::Key {
+            name: self.name.clone(),
+            instance: self.instance.clone(),
+            config: self.source.clone(),
+        }
+    }
+
+    fn segment(&self) -> Segment {
+        let mut segment = Segment::new(&self.name, &self.icon);
+        segment.instance = self.instance.clone();
+        segment.color = self.color.clone();
+        segment.separator = self.separator;
+        segment.min_width = self.min_width.clone();
+
+        self.on_click.iter().fold(segment, |segment, (button, action)| {
+            segment.on_click(*button, action.clone())
+        })
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some(config_home.join("hstatus").join("config.toml"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<Config, String> {
+        Config::parse(contents, None).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn default() {
+        let config = parse(DEFAULT).unwrap();
+        let names = config.segments.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["battery", "network", "clock"]);
+    }
+
+    #[test]
+    fn options() {
+        let config = parse(r#"
+            [[segment]]
+            source = "battery"
+            path = "/sys/class/power_supply/BAT1"
+            min_width = 100
+            on_click.scroll_up = { spawn = "true" }
+        "#).unwrap();
+
+        let segment = &config.segments[0];
+        assert_eq!(segment.min_width, Some(MinWidth::Pixels(100)));
+        assert_eq!(segment.on_click[&Button::ScrollUp], Action::Spawn("true".to_owned()));
+        assert_eq!(segment.source, source::Config::Battery(source::battery::Options {
+            alerts: source::battery::AlertOptions {
+                warn: 15,
+                critical: 5,
+                hysteresis: 2,
+                warn_color: "#ffaa00".to_owned(),
+                critical_color: "#ff0000".to_owned(),
+                critical_command: None,
+            },
+            backend: source::battery::Backend::Sysfs(source::battery::SysfsOptions {
+                path: Some(PathBuf::from("/sys/class/power_supply/BAT1")),
+                breakdown: false,
+                rate_window: 300,
+            }),
+        }));
+    }
+
+    #[test]
+    fn errors() {
+        let err = parse("[[segment]]\nsource = \"toaster\"").unwrap_err();
+        assert!(err.contains("unknown variant `toaster`"), "{}", err);
+
+        let err = parse("[[segment]]\nsource = \"clock\"\nfoo = 1").unwrap_err();
+        assert!(err.contains("clock source: unknown field `foo`"), "{}", err);
+
+        let err = parse("[[segment]]\nsource = \"battery\"\nbackend = \"upower\"\npath = \"/sys\"").unwrap_err();
+        assert!(err.contains("battery source: unknown field `path`"), "{}", err);
+
+        let err = parse("[[segment]]\nsource = \"battery\"\nwarn = 150").unwrap_err();
+        assert!(err.contains("battery source: warn is a percentage, but is 150"), "{}", err);
+
+        let err = parse("[[segment]]\nsource = \"battery\"\nwarn = 10\ncritical = 20").unwrap_err();
+        assert!(err.contains("battery source: critical (20) is above warn (10)"), "{}", err);
+
+        let err = parse("[[segment]]\nsource = \"battery\"\nperipherals = true").unwrap_err();
+        assert!(err.contains("battery source: unknown field `peripherals`"), "{}", err);
+
+        let err = parse("[[segment]]\nsource = \"battery\"\non_click.left = { message = \"cycle\" }").unwrap_err();
+        assert!(err.contains("does not accept messages"), "{}", err);
+
+        let err = parse("[[segment]]\nsource = \"clock\"\n[[segment]]\nsource = \"clock\"").unwrap_err();
+        assert!(err.contains("more than one segment named clock"), "{}", err);
+
+        let err = parse("colour = 1").unwrap_err();
+        assert!(err.contains("unknown field `colour`"), "{}", err);
+    }
+}
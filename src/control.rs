@@ -0,0 +1,349 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use crate::output;
+use crate::util::socket::{self, Peers};
+use crate::status::Block;
+
+/// Picks out segments by `name`, or by `name:instance` when several share
+/// a name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selector {
+    pub name: String,
+    pub instance: Option<String>,
+}
+
+impl Selector {
+    pub fn matches(&self, name: &str, instance: Option<&str>) -> bool {
+        self.name == name && (self.instance.is_none() || self.instance.as_deref() == instance)
+    }
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, instance) = match s.split_once(':') {
+            Some((name, instance)) => (name, Some(instance.to_owned())),
+            None => (s, None),
+        };
+
+        if name.is_empty() {
+            return Err("missing segment name".to_owned());
+        }
+
+        Ok(Selector { name: name.to_owned(), instance })
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.instance {
+            Some(instance) => write!(f, "{}:{}", self.name, instance),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// The line as shown and the value of each segment
+    Get,
+    /// Restart a segment's source so that it's read again
+    Refresh(Selector),
+    Hide(Selector),
+    Show(Selector),
+    /// Stop writing status lines until resumed
+    Pause,
+    Resume,
+    /// Reload the config
+    Reload,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or_else(|| "empty command".to_owned())?;
+        let argument = words.next();
+
+        if words.next().is_some() {
+            return Err(format!("too many arguments to {}", command));
+        }
+
+        let selector = || -> Result<Selector, String> {
+            argument.ok_or_else(|| format!("{} needs a segment", command))?.parse()
+        };
+
+        let no_argument = |command| match argument {
+            Some(_) => Err(format!("{} takes no arguments", line.trim())),
+            None => Ok(command),
+        };
+
+        match command {
+            "get" => no_argument(Command::Get),
+            "refresh" => Ok(Command::Refresh(selector()?)),
+            "hide" => Ok(Command::Hide(selector()?)),
+            "show" => Ok(Command::Show(selector()?)),
+            "pause" => no_argument(Command::Pause),
+            "resume" => no_argument(Command::Resume),
+            "reload" => no_argument(Command::Reload),
+            _ => Err(format!("unknown command: {}", command)),
+        }
+    }
+}
+
+/// The bar's ends of the channels driven by the control socket
+pub struct Bar {
+    pub refreshes: UnboundedReceiverStream<Selector>,
+    pub reloads: UnboundedReceiverStream<()>,
+    pub hidden: watch::Receiver<Vec<Selector>>,
+    pub paused: watch::Receiver<bool>,
+    /// The segments of the current config
+    pub segments: watch::Sender<Vec<Selector>>,
+    /// The blocks of the status line, before hiding any
+    pub status: watch::Sender<Vec<Block>>,
+    /// The line last written
+    pub shown: watch::Sender<Vec<Block>>,
+}
+
+/// Carries out commands sent to the control socket
+#[derive(Clone)]
+pub struct Control {
+    refreshes: mpsc::UnboundedSender<Selector>,
+    reloads: mpsc::UnboundedSender<()>,
+    hidden: Arc<watch::Sender<Vec<Selector>>>,
+    paused: Arc<watch::Sender<bool>>,
+    segments: watch::Receiver<Vec<Selector>>,
+    status: watch::Receiver<Vec<Block>>,
+    shown: watch::Receiver<Vec<Block>>,
+}
+
+pub fn channel() -> (Control, Bar) {
+    let (refreshes_tx, refreshes_rx) = mpsc::unbounded_channel();
+    let (reloads_tx, reloads_rx) = mpsc::unbounded_channel();
+    let (hidden_tx, hidden_rx) = watch::channel(Vec::new());
+    let (paused_tx, paused_rx) = watch::channel(false);
+    let (segments_tx, segments_rx) = watch::channel(Vec::new());
+    let (status_tx, status_rx) = watch::channel(Vec::new());
+    let (shown_tx, shown_rx) = watch::channel(Vec::new());
+
+    let control = Control {
+        refreshes: refreshes_tx,
+        reloads: reloads_tx,
+        hidden: Arc::new(hidden_tx),
+        paused: Arc::new(paused_tx),
+        segments: segments_rx,
+        status: status_rx,
+        shown: shown_rx,
+    };
+
+    let bar = Bar {
+        refreshes: UnboundedReceiverStream::new(refreshes_rx),
+        reloads: UnboundedReceiverStream::new(reloads_rx),
+        hidden: hidden_rx,
+        paused: paused_rx,
+        segments: segments_tx,
+        status: status_tx,
+        shown: shown_tx,
+    };
+
+    (control, bar)
+}
+
+impl Control {
+    /// Carries out `command`, returning the response as JSON
+    pub fn handle(&self, command: Command) -> Result<serde_json::Value, String> {
+        match command {
+            Command::Get => {
+                let hidden = self.hidden.borrow().iter()
+                    .map(Selector::to_string)
+                    .collect::<Vec<_>>();
+
+                Ok(json!({
+                    "line": output::text(&self.shown.borrow()),
+                    "segments": *self.status.borrow(),
+                    "hidden": hidden,
+                    "paused": *self.paused.borrow(),
+                }))
+            }
+            Command::Refresh(selector) => {
+                self.check(&selector)?;
+                let _ = self.refreshes.send(selector);
+                Ok(json!({}))
+            }
+            Command::Hide(selector) => {
+                self.check(&selector)?;
+                self.hidden.send_if_modified(|hidden| {
+                    let modified = !hidden.contains(&selector);
+                    if modified {
+                        hidden.push(selector);
+                    }
+                    modified
+                });
+                Ok(json!({}))
+            }
+            Command::Show(selector) => {
+                self.check(&selector)?;
+                self.hidden.send_if_modified(|hidden| {
+                    let len = hidden.len();
+                    hidden.retain(|hidden| *hidden != selector);
+                    hidden.len() != len
+                });
+                Ok(json!({}))
+            }
+            Command::Pause => {
+                self.paused.send_replace(true);
+                Ok(json!({}))
+            }
+            Command::Resume => {
+                self.paused.send_replace(false);
+                Ok(json!({}))
+            }
+            Command::Reload => {
+                let _ = self.reloads.send(());
+                Ok(json!({}))
+            }
+        }
+    }
+
+    /// Fails unless the selector picks out a segment of the current config
+    fn check(&self, selector: &Selector) -> Result<(), String> {
+        let exists = self.segments.borrow().iter()
+            .any(|segment| selector.matches(&segment.name, segment.instance.as_deref()));
+
+        match exists {
+            true => Ok(()),
+            false => Err(format!("no segment {}", selector)),
+        }
+    }
+}
+
+/// Where the bar listens for commands unless told otherwise
+pub fn default_path() -> Option<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+    Some(PathBuf::from(runtime_dir).join("hstatus-control.sock"))
+}
+
+/// How long to wait before accepting again after an accept fails, so
+/// running out of file descriptors doesn't spin
+const ACCEPT_RETRY: Duration = Duration::from_millis(500);
+
+/// Accepts `peers` for as long as the bar runs, answering each line they
+/// send with a line of JSON: `{"ok": true, ...}` or `{"ok": false, "error": ...}`
+pub async fn serve(listener: UnixListener, peers: Peers, control: Control) {
+    let mut clients = socket::incoming(listener, peers);
+
+    while let Some(socket) = clients.next().await {
+        match socket {
+            Ok(socket) => {
+                tokio::spawn(client(socket, control.clone()));
+            }
+            Err(e) => {
+                eprintln!("control: accept failed: {:?}", e);
+                tokio::time::sleep(ACCEPT_RETRY).await;
+            }
+        }
+    }
+}
+
+async fn client(socket: UnixStream, control: Control) {
+    let (reader, mut writer) = socket.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match line.parse().and_then(|command| control.handle(command)) {
+            Ok(mut response) => {
+                response["ok"] = json!(true);
+                response
+            }
+            Err(e) => json!({ "ok": false, "error": e }),
+        };
+
+        let mut response = response.to_string();
+        response.push('\n');
+
+        if writer.write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends a command to the bar listening on the socket at `path`, returning
+/// its response
+pub async fn send(path: &Path, command: &str) -> io::Result<String> {
+    let socket = socket::connect(path).await?;
+    let (reader, mut writer) = socket.into_split();
+
+    writer.write_all(format!("{}\n", command).as_bytes()).await?;
+    writer.shutdown().await?;
+
+    let mut response = String::new();
+    BufReader::new(reader).read_line(&mut response).await?;
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let battery = Selector { name: "battery".to_owned(), instance: None };
+        let utc = Selector { name: "clock".to_owned(), instance: Some("utc".to_owned()) };
+
+        assert_eq!("get".parse(), Ok(Command::Get));
+        assert_eq!(" refresh  battery ".parse(), Ok(Command::Refresh(battery.clone())));
+        assert_eq!("hide clock:utc".parse(), Ok(Command::Hide(utc.clone())));
+        assert!("hide".parse::<Command>().is_err());
+        assert!("pause now".parse::<Command>().is_err());
+        assert!("frobnicate".parse::<Command>().is_err());
+
+        assert!(battery.matches("battery", Some("bat0")));
+        assert!(utc.matches("clock", Some("utc")));
+        assert!(!utc.matches("clock", None));
+        assert_eq!(utc.to_string(), "clock:utc");
+    }
+
+    #[tokio::test]
+    async fn commands() {
+        let (control, bar) = channel();
+        let mut refreshes = bar.refreshes;
+
+        bar.segments.send_replace(vec!["battery".parse().unwrap()]);
+        bar.shown.send_replace(vec![Block::text("battery", "50%".to_owned())]);
+
+        assert_eq!(control.handle(Command::Get).unwrap()["line"], "50%");
+
+        control.handle(Command::Hide("battery".parse().unwrap())).unwrap();
+        control.handle(Command::Hide("battery".parse().unwrap())).unwrap();
+        assert_eq!(bar.hidden.borrow().len(), 1);
+        control.handle(Command::Show("battery".parse().unwrap())).unwrap();
+        assert!(bar.hidden.borrow().is_empty());
+
+        control.handle(Command::Refresh("battery".parse().unwrap())).unwrap();
+        assert_eq!(refreshes.next().await, Some("battery".parse().unwrap()));
+
+        assert!(control.handle(Command::Refresh("clock".parse().unwrap())).is_err());
+
+        control.handle(Command::Pause).unwrap();
+        assert!(*bar.paused.borrow());
+    }
+}
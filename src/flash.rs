@@ -1,44 +1,730 @@
-use std::time::Duration;
-use std::path::Path;
+use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::Duration;
+use std::path::{Path, PathBuf};
 
+use chrono::{DateTime, Local};
 use futures::{Stream, StreamExt};
-use tokio::io::{self, AsyncBufReadExt, BufReader};
+use serde::{Deserialize, Serialize};
+use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixListener;
-use tokio::sync::watch;
-use tokio_stream::wrappers::{WatchStream, UnixListenerStream, LinesStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::time::Instant;
+use tokio_stream::wrappers::{LinesStream, SignalStream};
+
+use crate::util::socket::{self, Peers};
+
+pub const FLASH_DURATION: Duration = Duration::from_secs(1);
+
+/// Longer durations are cut short, a message hides the status line after all
+pub const MAX_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// How many messages can wait behind the one being shown. Once full, the
+/// oldest message of the lowest priority is dropped.
+const MAX_QUEUED: usize = 16;
+
+/// How many past messages are kept for dumping and replaying
+const HISTORY_SIZE: usize = 100;
+
+/// Used by hstatus itself to flash messages, eg. config errors
+pub type Sender = mpsc::UnboundedSender<Message>;
+
+/// How flash messages are drawn, from the `[flash]` table of the config
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Style {
+    /// Number of glyphs in a bar
+    #[serde(default = "default_bar_width")]
+    pub bar_width: usize,
+    #[serde(default = "default_bar_full")]
+    pub bar_full: String,
+    #[serde(default = "default_bar_empty")]
+    pub bar_empty: String,
+}
+
+fn default_bar_width() -> usize {
+    10
+}
+
+fn default_bar_full() -> String {
+    "▓".to_owned()
+}
+
+fn default_bar_empty() -> String {
+    "░".to_owned()
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            bar_width: default_bar_width(),
+            bar_full: default_bar_full(),
+            bar_empty: default_bar_empty(),
+        }
+    }
+}
+
+/// A message flashed on the bar in place of the status line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub text: String,
+    /// A percentage drawn as a bar after the text, eg. for volume keys
+    pub value: Option<f64>,
+    /// Shown before the text
+    pub icon: Option<String>,
+    /// A message of the same kind as the one being shown or waiting
+    /// replaces it, and restarts its duration if it's being shown
+    pub kind: Option<String>,
+    pub duration: Duration,
+    /// Messages of higher priority are shown first, and can't be
+    /// preempted by messages of lower priority
+    pub priority: i32,
+    /// Wait for the current message to finish rather than replacing it
+    pub queue: bool,
+}
+
+impl Message {
+    pub fn new(text: impl Into<String>) -> Self {
+        Message {
+            text: text.into(),
+            value: None,
+            icon: None,
+            kind: None,
+            duration: FLASH_DURATION,
+            priority: 0,
+            queue: false,
+        }
+    }
+
+    pub fn render(&self, style: &Style) -> String {
+        let bar = self.value.map(|value| {
+            let full = (value / 100.0 * style.bar_width as f64).round() as usize;
+            let full = full.min(style.bar_width);
+
+            format!("{}{} {}%",
+                style.bar_full.repeat(full),
+                style.bar_empty.repeat(style.bar_width - full),
+                value.round())
+        });
+
+        self.icon.iter()
+            .chain(Some(&self.text).filter(|text| !text.is_empty()))
+            .chain(bar.as_ref())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Parses a line written to the flash socket. Lines starting with `{`
+    /// are JSON objects, eg. `{"text": "hi", "duration": 2.5, "priority": 1,
+    /// "queue": true}` or `{"icon": "🔊", "value": 50, "kind": "volume"}`,
+    /// which need either `text` or `value`. Any other line is shown as is.
+    pub fn parse(line: &str) -> Self {
+        if !line.trim_start().starts_with('{') {
+            return Message::new(line);
+        }
+
+        match serde_json::from_str::<Structured>(line) {
+            Ok(structured) => match structured.try_into() {
+                Ok(message) => message,
+                Err(e) => {
+                    eprintln!("flash: invalid message {:?}: {}", line, e);
+                    Message::new(line)
+                }
+            },
+            Err(e) => {
+                eprintln!("flash: invalid message {:?}: {}", line, e);
+                Message::new(line)
+            }
+        }
+    }
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Message::new(text)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Structured {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    icon: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    /// Seconds
+    duration: Option<f64>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    queue: bool,
+}
+
+impl TryFrom<Structured> for Message {
+    type Error = String;
+
+    fn try_from(structured: Structured) -> Result<Self, Self::Error> {
+        let duration = match structured.duration {
+            Some(duration) => Duration::try_from_secs_f64(duration)
+                .map_err(|e| format!("duration: {}", e))?
+                .min(MAX_DURATION),
+            None => FLASH_DURATION,
+        };
+
+        if structured.text.is_none() && structured.value.is_none() {
+            return Err("needs text or a value".to_owned());
+        }
+
+        let value = match structured.value {
+            Some(value) if !value.is_finite() => return Err("value is not a number".to_owned()),
+            value => value.map(|value| value.clamp(0.0, 100.0)),
+        };
 
-use crate::util::future::defer;
+        Ok(Message {
+            text: structured.text.unwrap_or_default(),
+            value,
+            icon: structured.icon,
+            kind: structured.kind,
+            duration,
+            priority: structured.priority,
+            queue: structured.queue,
+        })
+    }
+}
+
+impl From<&Message> for Structured {
+    fn from(message: &Message) -> Self {
+        Structured {
+            text: Some(message.text.clone()),
+            value: message.value,
+            icon: message.icon.clone(),
+            kind: message.kind.clone(),
+            duration: Some(message.duration.as_secs_f64()),
+            priority: message.priority,
+            queue: message.queue,
+        }
+    }
+}
+
+/// What clients of the flash socket can ask for
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Request {
+    #[serde(skip)]
+    Flash(Message),
+    /// Show the last N messages again
+    Replay(usize),
+    /// Send the history of messages back
+    Dump,
+}
+
+/// A request, and where the history goes if it's a dump: back to the
+/// client that asked for it, or to stderr if there's none
+pub struct Incoming {
+    pub request: Request,
+    pub reply: Option<oneshot::Sender<String>>,
+}
+
+impl From<Request> for Incoming {
+    fn from(request: Request) -> Self {
+        Incoming { request, reply: None }
+    }
+}
+
+impl Request {
+    /// Parses a line written to the flash socket: either `{"replay": N}`,
+    /// `"dump"` or a message, see `Message::parse`
+    pub fn parse(line: &str) -> Self {
+        match serde_json::from_str::<Request>(line) {
+            Ok(request) => request,
+            Err(_) => Request::Flash(Message::parse(line)),
+        }
+    }
 
-const FLASH_DURATION: Duration = Duration::from_secs(1);
+    fn to_line(&self) -> serde_json::Result<String> {
+        let mut line = match self {
+            // always sent as JSON, so the text can't be mistaken for it
+            Request::Flash(message) => serde_json::to_string(&Structured::from(message))?,
+            request => serde_json::to_string(request)?,
+        };
 
-pub fn bind(path: &Path) -> Result<impl Stream<Item = Option<String>>, io::Error> {
-    // delete socket path before trying to bind it
-    let _ = std::fs::remove_file(path);
+        line.push('\n');
+        Ok(line)
+    }
+}
+
+/// Asks for the history to be dumped to stderr on SIGUSR2
+pub fn dumps() -> impl Stream<Item = Incoming> {
+    let signals = signal(SignalKind::user_defined2())
+        .map(SignalStream::new)
+        .map_err(|e| eprintln!("flash: could not handle SIGUSR2: {:?}", e))
+        .ok();
+
+    futures::stream::iter(signals)
+        .flatten()
+        .map(|()| Request::Dump.into())
+}
+
+/// Where the bar listens for messages unless told otherwise
+pub fn default_path() -> Option<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+    Some(PathBuf::from(runtime_dir).join("hstatus.sock"))
+}
+
+/// Sends a request to the bar listening on the socket at `path`
+pub async fn send(path: &Path, request: &Request) -> Result<(), io::Error> {
+    let line = request.to_line()?;
+
+    let mut socket = socket::connect(path).await?;
+    socket.write_all(line.as_bytes()).await?;
+    socket.shutdown().await
+}
 
-    let listener = UnixListener::bind(path)?;
+/// Asks the bar listening on the socket at `path` for its history
+pub async fn dump(path: &Path) -> Result<String, io::Error> {
+    let line = Request::Dump.to_line()?;
 
-    let (tx, rx) = watch::channel(());
+    let mut socket = socket::connect(path).await?;
+    socket.write_all(line.as_bytes()).await?;
+    socket.shutdown().await?;
 
-    let lines = UnixListenerStream::new(listener)
+    let mut dump = String::new();
+    socket.read_to_string(&mut dump).await?;
+    Ok(dump)
+}
+
+/// Requests written by `peers` connecting to `listener`, one per line
+pub fn serve(listener: UnixListener, peers: Peers) -> impl Stream<Item = Incoming> {
+    socket::incoming(listener, peers)
         .filter_map(|socket| async { socket.ok() })
-        .map(|socket| BufReader::new(socket))
-        .flat_map_unordered(None, |reader| LinesStream::new(reader.lines()))
-        .filter_map(|line| async { line.ok() })
-        .inspect({
-            let tx = Arc::new(tx);
-            let mut _cancel = None;
-
-            move |_| {
-                let tx = tx.clone();
-                _cancel = Some(defer(FLASH_DURATION, async move {
-                    let _ = tx.send(());
-                }));
+        .flat_map_unordered(None, |socket| {
+            let (reader, writer) = socket.into_split();
+            let writer = Arc::new(Mutex::new(writer));
+
+            LinesStream::new(BufReader::new(reader).lines())
+                .filter_map(|line| futures::future::ready(line.ok()))
+                .map(move |line| {
+                    let request = Request::parse(&line);
+
+                    let reply = (request == Request::Dump).then(|| {
+                        let (tx, rx) = oneshot::channel::<String>();
+                        let writer = writer.clone();
+
+                        // the connection stays open until the history is sent
+                        tokio::spawn(async move {
+                            if let Ok(dump) = rx.await {
+                                let _ = writer.lock().await.write_all(dump.as_bytes()).await;
+                            }
+                        });
+
+                        tx
+                    });
+
+                    Incoming { request, reply }
+                })
+        })
+}
+
+/// Shows each message for its duration in the current style, yielding
+/// `None` once the last one expires
+pub fn display(requests: impl Stream<Item = Incoming>, style: watch::Receiver<Style>)
+    -> impl Stream<Item = Option<String>>
+{
+    let state = (Queue::default(), History::default(), Some(Box::pin(requests)));
+
+    futures::stream::unfold(state, move |(mut queue, mut history, mut requests)| {
+        let style = style.clone();
+
+        async move {
+            loop {
+                let deadline = queue.deadline();
+
+                tokio::select! {
+                    request = next(&mut requests) => {
+                        let before = queue.current().cloned();
+
+                        match request.map(|incoming| (incoming.request, incoming.reply)) {
+                            Some((Request::Flash(message), _)) => {
+                                history.push(&message);
+                                queue.push(message, Instant::now());
+                            }
+                            Some((Request::Replay(count), _)) => {
+                                for message in history.last(count) {
+                                    // without a kind, so they don't replace each other
+                                    queue.push(Message { queue: true, kind: None, ..message.clone() }, Instant::now());
+                                }
+                            }
+                            Some((Request::Dump, reply)) => {
+                                let dump = history.dump(&style.borrow());
+
+                                match reply {
+                                    Some(reply) => { let _ = reply.send(dump); }
+                                    None => eprint!("{}", dump),
+                                }
+                            }
+                            None => requests = None,
+                        }
+
+                        if queue.current() != before.as_ref() {
+                            break;
+                        }
+                    }
+                    _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                        queue.expire(Instant::now());
+                        break;
+                    }
+                }
             }
+
+            let text = queue.current().map(|message| message.render(&style.borrow()));
+            Some((text, (queue, history, requests)))
+        }
+    })
+}
+
+/// The next request, or never once the requests have ended
+async fn next(requests: &mut Option<impl Stream<Item = Incoming> + Unpin>) -> Option<Incoming> {
+    match requests {
+        Some(requests) => requests.next().await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// The message being shown and those waiting behind it
+#[derive(Default)]
+struct Queue {
+    current: Option<(Message, Instant)>,
+    /// Ordered by priority, highest first, then by arrival
+    pending: VecDeque<Message>,
+}
+
+impl Queue {
+    fn push(&mut self, message: Message, now: Instant) {
+        self.expire(now);
+        self.insert(message, now);
+        // messages without a duration are never shown
+        self.expire(now);
+    }
+
+    fn insert(&mut self, message: Message, now: Instant) {
+        if message.kind.is_some() {
+            if let Some((current, deadline)) = &mut self.current {
+                if current.kind == message.kind {
+                    *deadline = deadline_after(now, message.duration);
+                    *current = message;
+                    return;
+                }
+            }
+
+            // the replacement may not belong in the same place
+            self.pending.retain(|pending| pending.kind != message.kind);
+        }
+
+        match &self.current {
+            None => self.show(message, now),
+            Some((current, _)) if !message.queue && message.priority >= current.priority => {
+                self.show(message, now);
+            }
+            Some(_) => {
+                let index = self.pending.iter()
+                    .position(|pending| pending.priority < message.priority)
+                    .unwrap_or(self.pending.len());
+
+                self.pending.insert(index, message);
+
+                if self.pending.len() > MAX_QUEUED {
+                    let lowest = self.pending.back().map(|message| message.priority);
+                    let oldest = self.pending.iter()
+                        .position(|message| Some(message.priority) == lowest);
+
+                    if let Some(oldest) = oldest {
+                        self.pending.remove(oldest);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Moves on to the next message once the current one has been shown for
+    /// its duration
+    fn expire(&mut self, now: Instant) {
+        while let Some((_, deadline)) = &self.current {
+            if *deadline > now {
+                break;
+            }
+
+            self.current = None;
+
+            if let Some(next) = self.pending.pop_front() {
+                self.show(next, now);
+            }
+        }
+    }
+
+    fn show(&mut self, message: Message, now: Instant) {
+        let deadline = deadline_after(now, message.duration);
+        self.current = Some((message, deadline));
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.current.as_ref().map(|(_, deadline)| *deadline)
+    }
+
+    fn current(&self) -> Option<&Message> {
+        self.current.as_ref().map(|(message, _)| message)
+    }
+}
+
+/// Durations are already capped when parsed, but messages can be made
+/// without parsing
+fn deadline_after(now: Instant, duration: Duration) -> Instant {
+    now.checked_add(duration.min(MAX_DURATION)).unwrap_or(now)
+}
+
+/// Recently flashed messages, oldest first
+#[derive(Default)]
+struct History {
+    entries: VecDeque<(DateTime<Local>, Message)>,
+}
+
+impl History {
+    fn push(&mut self, message: &Message) {
+        // messages closing another, eg. a notification
+        if message.duration.is_zero() {
+            return;
+        }
+
+        if self.entries.len() == HISTORY_SIZE {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back((Local::now(), message.clone()));
+    }
+
+    fn last(&self, count: usize) -> impl Iterator<Item = &Message> {
+        let skip = self.entries.len().saturating_sub(count);
+        self.entries.iter().skip(skip).map(|(_, message)| message)
+    }
+
+    /// One line per message with the time it was flashed
+    fn dump(&self, style: &Style) -> String {
+        self.entries.iter()
+            .map(|(time, message)| format!("{} {}\n", time.format("%Y-%m-%d %H:%M:%S"), message.render(style)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(queue: &Queue) -> Option<&str> {
+        queue.current().map(|message| message.text.as_str())
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Message::parse("hello"), Message::new("hello"));
+
+        let message = Message::parse(r#"{"text": "hi", "duration": 2.5, "priority": -1, "queue": true}"#);
+        assert_eq!(message, Message {
+            duration: Duration::from_millis(2500),
+            priority: -1,
+            queue: true,
+            ..Message::new("hi")
         });
 
-    let lines = lines.map(Some);
-    let clears = WatchStream::new(rx).map(|()| None);
+        let message = Message::parse(r#"{"icon": "🔊", "value": 120, "kind": "volume"}"#);
+        assert_eq!(message.value, Some(100.0));
+        assert_eq!(message.kind.as_deref(), Some("volume"));
+
+        assert_eq!(Message::parse(r#"{"priority": 1}"#).text, r#"{"priority": 1}"#);
+
+        assert_eq!(Message::parse(r#"{"text": "hi", "duration": -1}"#).text, r#"{"text": "hi", "duration": -1}"#);
+        assert_eq!(Message::parse(r#"{"text": "hi", "duration": 1e19}"#).duration, MAX_DURATION);
+        assert_eq!(Message::parse("{not json").text, "{not json");
+
+        let message = Message { text: "{not json".to_owned(), ..message };
+        let request = Request::Flash(message);
+        assert_eq!(Request::parse(&request.to_line().unwrap()), request);
+    }
+
+    #[test]
+    fn requests() {
+        assert_eq!(Request::parse(r#"{"replay": 3}"#), Request::Replay(3));
+        assert_eq!(Request::parse(r#""dump""#), Request::Dump);
+        assert_eq!(Request::parse(r#"{"dump": "/tmp/flash"}"#), Request::Flash(Message::new(r#"{"dump": "/tmp/flash"}"#)));
+        assert_eq!(Request::Dump.to_line().unwrap(), "\"dump\"\n");
+        assert_eq!(Request::parse(r#"{"replay": -1}"#), Request::Flash(Message::new(r#"{"replay": -1}"#)));
+        assert_eq!(Request::Replay(2).to_line().unwrap(), "{\"replay\":2}\n");
+    }
+
+    #[test]
+    fn history() {
+        let mut history = History::default();
+
+        for i in 0..HISTORY_SIZE + 2 {
+            history.push(&Message::new(format!("message {}", i)));
+        }
+
+        history.push(&Message { duration: Duration::ZERO, ..Message::new("closed") });
+
+        let last = history.last(2).map(|message| message.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(last, [format!("message {}", HISTORY_SIZE), format!("message {}", HISTORY_SIZE + 1)]);
+        assert_eq!(history.last(1000).count(), HISTORY_SIZE);
+
+        let dump = history.dump(&Style::default());
+        assert_eq!(dump.lines().count(), HISTORY_SIZE);
+        assert!(dump.lines().next().unwrap().ends_with(" message 2"));
+    }
+
+    #[test]
+    fn queue() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut queue = Queue::default();
+
+        queue.push(Message::new("first"), at(0));
+        queue.push(Message { queue: true, ..Message::new("queued") }, at(100));
+        queue.push(Message { queue: true, priority: 1, ..Message::new("important") }, at(200));
+        assert_eq!(text(&queue), Some("first"));
+
+        queue.expire(at(1000));
+        assert_eq!(text(&queue), Some("important"));
+
+        // lower priority messages wait even if they don't ask to
+        queue.push(Message::new("preempt"), at(1100));
+        assert_eq!(text(&queue), Some("important"));
+
+        queue.expire(at(2000));
+        assert_eq!(text(&queue), Some("queued"));
+
+        queue.push(Message { priority: 1, ..Message::new("preempt") }, at(2100));
+        assert_eq!(text(&queue), Some("preempt"));
+
+        queue.expire(at(3100));
+        assert_eq!(text(&queue), Some("preempt"));
+        queue.expire(at(4100));
+        assert_eq!(text(&queue), None);
+    }
+
+    #[test]
+    fn render() {
+        let style = Style::default();
+        let message = Message { icon: Some("🔊".to_owned()), value: Some(50.0), ..Message::new("") };
+        assert_eq!(message.render(&style), "🔊 ▓▓▓▓▓░░░░░ 50%");
+
+        let style = Style { bar_width: 4, bar_full: "#".to_owned(), bar_empty: "-".to_owned() };
+        let message = Message { value: Some(33.0), ..Message::new("Brightness") };
+        assert_eq!(message.render(&style), "Brightness #--- 33%");
+
+        assert_eq!(Message::new("plain").render(&style), "plain");
+    }
+
+    #[test]
+    fn kinds() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let volume = |value| Message { value: Some(value), kind: Some("volume".to_owned()), ..Message::new("") };
+        let mut queue = Queue::default();
+
+        queue.push(volume(10.0), at(0));
+        queue.push(volume(20.0), at(800));
+        assert_eq!(queue.current().unwrap().value, Some(20.0));
+        assert_eq!(queue.deadline(), Some(at(1800)));
+        assert!(queue.pending.is_empty());
+
+        queue.push(Message { priority: 1, ..Message::new("important") }, at(900));
+        queue.push(volume(30.0), at(1000));
+        queue.push(volume(40.0), at(1100));
+        assert_eq!(text(&queue), Some("important"));
+        assert_eq!(queue.pending.len(), 1);
+        assert_eq!(queue.pending[0].value, Some(40.0));
+
+        // closing a message of a kind
+        queue.push(Message { kind: Some("volume".to_owned()), duration: Duration::ZERO, ..Message::new("") }, at(1200));
+        queue.expire(at(1900));
+        assert_eq!(text(&queue), None);
+    }
+
+    #[test]
+    fn kinds_across_priorities() {
+        let now = Instant::now();
+        let volume = |priority| Message { priority, kind: Some("volume".to_owned()), ..Message::new("volume") };
+        let pending = |queue: &Queue| queue.pending.iter().map(|message| message.text.clone()).collect::<Vec<_>>();
+        let mut queue = Queue::default();
+
+        queue.push(Message { priority: 2, ..Message::new("alarm") }, now);
+        queue.push(Message::new("mail"), now);
+        queue.push(volume(0), now);
+        assert_eq!(pending(&queue), ["mail", "volume"]);
+
+        // an urgent replacement moves ahead of what it now outranks
+        queue.push(volume(1), now);
+        assert_eq!(pending(&queue), ["volume", "mail"]);
+        assert_eq!(queue.pending[0].priority, 1);
+
+        // and a less urgent one falls back behind it
+        queue.push(volume(0), now);
+        assert_eq!(pending(&queue), ["mail", "volume"]);
+        assert_eq!(text(&queue), Some("alarm"));
+    }
+
+    #[tokio::test]
+    async fn replay_and_dump() {
+        let volume = |text| Message {
+            kind: Some("volume".to_owned()),
+            duration: Duration::from_millis(50),
+            ..Message::new(text)
+        };
+
+        let (dump_tx, dump_rx) = oneshot::channel();
+
+        let requests = futures::stream::iter([
+            Request::Flash(volume("10%")).into(),
+            Request::Flash(volume("20%")).into(),
+            Request::Replay(2).into(),
+            Incoming { request: Request::Dump, reply: Some(dump_tx) },
+        ]);
+
+        let (_, style) = watch::channel(Style::default());
+        let shown = display(requests, style).take(4).collect::<Vec<_>>().await;
+
+        // the replayed messages don't replace each other like the originals
+        assert_eq!(shown, [Some("10%"), Some("20%"), Some("10%"), Some("20%")].map(|text| text.map(str::to_owned)));
+        assert_eq!(dump_rx.await.unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn long_durations() {
+        let now = Instant::now();
+        let mut queue = Queue::default();
+
+        queue.push(Message { duration: Duration::MAX, ..Message::new("forever") }, now);
+        assert_eq!(queue.deadline(), Some(now + MAX_DURATION));
+    }
+
+    #[test]
+    fn bounded() {
+        let now = Instant::now();
+        let mut queue = Queue::default();
+
+        queue.push(Message::new("current"), now);
+        queue.push(Message { queue: true, priority: 1, ..Message::new("keep") }, now);
+
+        for i in 0..MAX_QUEUED {
+            queue.push(Message { queue: true, ..Message::new(format!("burst {}", i)) }, now);
+        }
 
-    Ok(futures::stream::select(lines, clears))
+        assert_eq!(queue.pending.len(), MAX_QUEUED);
+        assert_eq!(queue.pending[0].text, "keep");
+        assert_eq!(queue.pending[1].text, "burst 1");
+        assert_eq!(queue.pending[MAX_QUEUED - 1].text, format!("burst {}", MAX_QUEUED - 1));
+    }
 }
@@ -1,51 +1,402 @@
+mod click;
+mod config;
+mod control;
 mod flash;
+mod notifications;
+mod output;
+mod reload;
 mod source;
 mod status;
 mod util;
 
+use std::io;
 use std::path::{Path, PathBuf};
-use futures::future::Either;
+use std::time::Duration;
+use futures::future::{self, Either};
 use futures::stream::{self, Stream, StreamExt};
 use structopt::StructOpt;
+use tokio::net::UnixListener;
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::{UnboundedReceiverStream, WatchStream};
+
+use config::Config;
+use status::Block;
 
 #[derive(StructOpt)]
 struct Opt {
-    #[structopt(short, long)]
+    /// Path to the socket flash messages are sent to, defaults to
+    /// $XDG_RUNTIME_DIR/hstatus.sock. A leading @ names an abstract socket.
+    #[structopt(short, long, global = true)]
     socket: Option<PathBuf>,
+
+    /// Path to the control socket, defaults to
+    /// $XDG_RUNTIME_DIR/hstatus-control.sock
+    #[structopt(long, global = true)]
+    control: Option<PathBuf>,
+
+    /// Also accept flash messages and commands from this user ID. Only our
+    /// own user can connect otherwise.
+    #[structopt(long = "allow-uid", number_of_values = 1)]
+    allow_uids: Vec<u32>,
+
+    /// Path to the config file, defaults to $XDG_CONFIG_HOME/hstatus/config.toml
+    #[structopt(short, long)]
+    config: Option<PathBuf>,
+
+    /// Output format, either `text` or `i3bar`
+    #[structopt(short, long, default_value = "text")]
+    format: output::Format,
+
+    /// Show desktop notifications as flash messages, unless another
+    /// notification daemon is running
+    #[structopt(long)]
+    notifications: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Flashes a message on a running bar. Exits with status 69 if no bar is
+    /// listening.
+    Flash {
+        /// Text of the message, optional with --value
+        #[structopt(required_unless = "value")]
+        text: Option<String>,
+
+        /// A percentage to draw as a bar, eg. the volume
+        #[structopt(short, long)]
+        value: Option<f64>,
+
+        /// Shown before the text
+        #[structopt(short, long)]
+        icon: Option<String>,
+
+        /// Replace the message of this kind being shown, if any, rather
+        /// than queueing behind it
+        #[structopt(short, long)]
+        kind: Option<String>,
+
+        /// Seconds to show the message for, at most an hour
+        #[structopt(short, long, parse(try_from_str = parse_seconds))]
+        duration: Option<Duration>,
+
+        /// Messages of higher priority are shown first
+        #[structopt(short, long, default_value = "0", allow_hyphen_values = true)]
+        priority: i32,
+
+        /// Wait for the current message to finish rather than replacing it
+        #[structopt(short, long)]
+        queue: bool,
+    },
+
+    /// Shows the last messages flashed on a running bar again
+    Replay {
+        #[structopt(default_value = "1")]
+        count: usize,
+    },
+
+    /// Prints the messages recently flashed on a running bar, or writes
+    /// them to a file. Sending the bar SIGUSR2 writes them to its stderr.
+    Dump {
+        path: Option<PathBuf>,
+    },
+
+    /// Sends a command to a running bar and prints its JSON response: get,
+    /// refresh SEGMENT, hide SEGMENT, show SEGMENT, pause, resume or reload.
+    /// SEGMENT is a name, or name:instance.
+    Control {
+        #[structopt(required = true)]
+        command: Vec<String>,
+    },
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds = s.parse::<f64>().map_err(|e| e.to_string())?;
+    let duration = Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())?;
+    Ok(duration.min(flash::MAX_DURATION))
 }
 
+/// EX_UNAVAILABLE from sysexits.h
+const EXIT_NO_BAR: i32 = 69;
+
 #[tokio::main]
 async fn main() {
     let opt = Opt::from_args();
+    let socket = opt.socket.clone().or_else(flash::default_path);
+    let control_socket = opt.control.clone().or_else(control::default_path);
+
+    if let Some(command) = opt.command {
+        let socket = socket.as_deref();
+
+        let status = match command {
+            Command::Flash { text, value, icon, kind, duration, priority, queue } => {
+                let message = flash::Message {
+                    value,
+                    icon,
+                    kind,
+                    duration: duration.unwrap_or(flash::FLASH_DURATION),
+                    priority,
+                    queue,
+                    ..flash::Message::new(text.unwrap_or_default())
+                };
+
+                send_flash(socket, &flash::Request::Flash(message)).await
+            }
+            Command::Replay { count } => send_flash(socket, &flash::Request::Replay(count)).await,
+            Command::Dump { path } => dump(socket, path.as_deref()).await,
+            Command::Control { command } => send_control(control_socket.as_deref(), &command.join(" ")).await,
+        };
+
+        std::process::exit(status);
+    }
+
+    let config = match Config::load(opt.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("hstatus: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let (flash_tx, flash_rx) = mpsc::unbounded_channel();
+
+    let (control, bar) = control::channel();
+
+    let configs = stream::once(future::ready(config))
+        .chain(reloads(opt.config.clone(), flash_tx.clone(), bar.reloads));
+
+    let events = stream::select(
+        configs.map(Event::Config),
+        bar.refreshes.map(Event::Refresh),
+    );
+
+    let (clicks_tx, clicks_rx) = watch::channel(click::Handler::default());
+    let (style_tx, style_rx) = watch::channel(flash::Style::default());
+
+    let status = util::stream::follow_latest(events.filter_map({
+        let mut pool = source::Pool::new(flash_tx.clone());
+        let mut current = None;
+        let segments = bar.segments;
+
+        move |event| {
+            let config = match event {
+                Event::Config(config) => {
+                    segments.send_replace(config.selectors());
+                    style_tx.send_replace(config.flash.clone());
+                    current.insert(config)
+                }
+                Event::Refresh(selector) => match &mut current {
+                    Some(config) => {
+                        config.restart(&mut pool, &selector);
+                        config
+                    }
+                    None => return future::ready(None),
+                },
+            };
+
+            let (line, clicks) = config.line_builder(&mut pool).build();
+            clicks_tx.send_replace(clicks);
+            future::ready(Some(line))
+        }
+    }));
+
+    let status = status.inspect(move |line| {
+        bar.status.send_replace(line.clone());
+    });
+
+    let status = util::stream::combine(status, WatchStream::new(bar.hidden))
+        .map(|(line, hidden)| {
+            let hidden = hidden.unwrap_or_default();
 
-    let status = status::LineBuilder::new()
-        .segment("🔋 ", source::battery::auto())
-        .segment("📶 ", source::wifi::networkmanager::network())
-        .segment("🕒 ", source::clock::clock())
-        .build();
+            line.unwrap_or_default().into_iter()
+                .filter(|block| !hidden.iter().any(|selector| selector.matches(&block.name, block.instance.as_deref())))
+                .collect::<Vec<_>>()
+        });
 
-    let flash = flash(opt.socket.as_deref());
+    let peers = util::socket::Peers::new(opt.allow_uids);
+
+    if let Some(listener) = listen("control", control_socket.as_deref()) {
+        tokio::spawn(control::serve(listener, peers.clone(), control));
+    }
+
+    if opt.notifications {
+        tokio::spawn(notifications::run(flash_tx.clone()));
+    }
+
+    if opt.format == output::Format::I3bar {
+        tokio::spawn(click::run(clicks_rx, tokio::io::stdin()));
+    }
+
+    let flash = flash::display(stream::select(
+        stream::select(flash(socket.as_deref(), peers), flash::dumps()),
+        UnboundedReceiverStream::new(flash_rx).map(|message| flash::Request::Flash(message).into()),
+    ), style_rx);
 
     let display = merge_flash(flash, status);
 
+    // while paused, the latest line is held back until resumed
+    let display = util::stream::combine(display, WatchStream::new(bar.paused))
+        .filter_map(|(line, paused)| future::ready(match paused {
+            Some(true) => None,
+            _ => line,
+        }));
+
     futures::pin_mut!(display);
 
+    let mut output = output::Output::new(opt.format, std::io::stdout());
+    output.header().expect("write header");
+
     while let Some(line) = display.next().await {
-        println!("{}", line);
+        output.line(&line).expect("write status line");
+        bar.shown.send_replace(line);
     }
 }
 
+/// What the status line is built from
+enum Event {
+    Config(Config),
+    /// Restart the sources of some segments
+    Refresh(control::Selector),
+}
+
+/// Reloads the config when requested, including by `requests`, flashing any
+/// errors and keeping the current config running.
+fn reloads(path: Option<PathBuf>, flash: flash::Sender, requests: impl Stream<Item = ()>) -> impl Stream<Item = Config> {
+    let watch_path = path.clone().or_else(config::default_path);
+
+    stream::select(reload::triggers(watch_path.as_deref()), requests)
+        .filter_map(move |()| future::ready(match Config::load(path.as_deref()) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                eprintln!("hstatus: {}", e);
+                let _ = flash.send(format!("hstatus: {}", e).into());
+                None
+            }
+        }))
+}
+
 fn merge_flash(
     flash: impl Stream<Item = Option<String>>,
-    stream: impl Stream<Item = String>,
-) -> impl Stream<Item = String> {
+    stream: impl Stream<Item = Vec<Block>>,
+) -> impl Stream<Item = Vec<Block>> {
     util::stream::combine(flash, stream)
-        .map(|(flash, line)| flash.flatten().or(line).unwrap_or_default())
+        .map(|(flash, line)| match flash.flatten() {
+            Some(flash) => vec![Block::text("flash", flash)],
+            None => line.unwrap_or_default(),
+        })
 }
 
-fn flash(path: Option<&Path>) -> impl Stream<Item = Option<String>> {
-    match path {
-        Some(path) => Either::Left(flash::bind(path).expect("flash::bind")),
+fn flash(path: Option<&Path>, peers: util::socket::Peers) -> impl Stream<Item = flash::Incoming> {
+    match listen("flash", path) {
+        Some(listener) => Either::Left(flash::serve(listener, peers)),
         None => Either::Right(stream::empty()),
     }
 }
+
+/// The socket systemd passed us under `name`, or else one bound at `path`
+fn listen(name: &str, path: Option<&Path>) -> Option<UnixListener> {
+    if let Some(listener) = util::socket::activated(name) {
+        return listener
+            .map_err(|e| eprintln!("hstatus: could not use the {} socket from systemd: {}", name, e))
+            .ok();
+    }
+
+    let path = path?;
+
+    match util::socket::bind(path) {
+        Ok(listener) => Some(listener),
+        // eg. a bar on each output, all with the default paths
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            let option = if name == "flash" { "--socket" } else { "--control" };
+            eprintln!("hstatus: another bar is listening on {}, pass {} to listen elsewhere", path.display(), option);
+            None
+        }
+        Err(e) => {
+            eprintln!("hstatus: could not listen on {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Sends a command to a running bar and prints the response, returning the
+/// exit status
+async fn send_control(path: Option<&Path>, command: &str) -> i32 {
+    let Some(path) = path else {
+        eprintln!("hstatus: no control socket path, pass --control or set XDG_RUNTIME_DIR");
+        return EXIT_NO_BAR;
+    };
+
+    match control::send(path, command).await {
+        Ok(response) => {
+            print!("{}", response);
+
+            let ok = serde_json::from_str::<serde_json::Value>(&response)
+                .map(|response| response["ok"] == true)
+                .unwrap_or(false);
+
+            if ok { 0 } else { 1 }
+        }
+        Err(e) => send_error(path, e),
+    }
+}
+
+/// Sends a request to a running bar, returning the exit status
+async fn send_flash(path: Option<&Path>, request: &flash::Request) -> i32 {
+    let Some(path) = path else {
+        eprintln!("hstatus: no socket path, pass --socket or set XDG_RUNTIME_DIR");
+        return EXIT_NO_BAR;
+    };
+
+    match flash::send(path, request).await {
+        Ok(()) => 0,
+        Err(e) => send_error(path, e),
+    }
+}
+
+/// Fetches the history of a running bar and prints it or writes it to
+/// `file`, returning the exit status
+async fn dump(path: Option<&Path>, file: Option<&Path>) -> i32 {
+    let Some(path) = path else {
+        eprintln!("hstatus: no socket path, pass --socket or set XDG_RUNTIME_DIR");
+        return EXIT_NO_BAR;
+    };
+
+    let dump = match flash::dump(path).await {
+        Ok(dump) => dump,
+        Err(e) => return send_error(path, e),
+    };
+
+    let written = match file {
+        Some(file) => tokio::fs::write(file, dump).await
+            .map_err(|e| format!("{}: {}", file.display(), e)),
+        None => {
+            print!("{}", dump);
+            Ok(())
+        }
+    };
+
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("hstatus: {}", e);
+            1
+        }
+    }
+}
+
+/// Reports a failure to reach the bar, returning the exit status shared by
+/// all the clients
+fn send_error(path: &Path, e: io::Error) -> i32 {
+    match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+            eprintln!("hstatus: no bar listening on {}", path.display());
+            EXIT_NO_BAR
+        }
+        _ => {
+            eprintln!("hstatus: could not send to {}: {}", path.display(), e);
+            1
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use zbus::fdo::RequestNameFlags;
+use zbus::zvariant::OwnedValue;
+use zbus::{dbus_interface, ConnectionBuilder, SignalContext};
+
+use crate::flash::{self, Message};
+
+const NAME: &str = "org.freedesktop.Notifications";
+const PATH: &str = "/org/freedesktop/Notifications";
+
+/// How long notifications are shown unless they say otherwise
+const DEFAULT_DURATION: Duration = Duration::from_secs(5);
+
+/// Notifications hide the status line while they're shown, so even those
+/// asking to never expire go away after this long
+const MAX_DURATION: Duration = Duration::from_secs(30);
+
+/// Reason given in NotificationClosed when CloseNotification was called
+const CLOSED_BY_CALL: u32 = 3;
+
+/// A notification server showing notifications as flash messages, see the
+/// Desktop Notifications Specification
+struct Server {
+    flash: flash::Sender,
+    last_id: u32,
+}
+
+#[dbus_interface(name = "org.freedesktop.Notifications")]
+impl Server {
+    fn get_capabilities(&self) -> Vec<String> {
+        vec!["body".to_owned()]
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &mut self,
+        _app_name: String,
+        replaces_id: u32,
+        _app_icon: String,
+        summary: String,
+        body: String,
+        _actions: Vec<String>,
+        hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> u32 {
+        let id = match replaces_id {
+            0 => {
+                self.last_id = self.last_id.wrapping_add(1).max(1);
+                self.last_id
+            }
+            id => id,
+        };
+
+        let urgency = hints.get("urgency")
+            .and_then(|urgency| u8::try_from(urgency).ok())
+            .unwrap_or(URGENCY_NORMAL);
+
+        let _ = self.flash.send(message(id, &summary, &body, urgency, expire_timeout));
+
+        id
+    }
+
+    async fn close_notification(&self, #[zbus(signal_context)] ctx: SignalContext<'_>, id: u32) {
+        // replacing the notification with an empty message of the same
+        // kind that expires straight away takes it off the bar
+        let _ = self.flash.send(Message {
+            kind: Some(kind(id)),
+            duration: Duration::ZERO,
+            ..Message::new("")
+        });
+
+        if let Err(e) = Server::notification_closed(&ctx, id, CLOSED_BY_CALL).await {
+            eprintln!("notifications: {:?}", e);
+        }
+    }
+
+    fn get_server_information(&self) -> (String, String, String, String) {
+        (
+            "hstatus".to_owned(),
+            "hstatus".to_owned(),
+            env!("CARGO_PKG_VERSION").to_owned(),
+            "1.2".to_owned(),
+        )
+    }
+
+    #[dbus_interface(signal)]
+    async fn notification_closed(ctx: &SignalContext<'_>, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+const URGENCY_LOW: u8 = 0;
+const URGENCY_NORMAL: u8 = 1;
+const URGENCY_CRITICAL: u8 = 2;
+
+fn kind(id: u32) -> String {
+    format!("notification {}", id)
+}
+
+fn message(id: u32, summary: &str, body: &str, urgency: u8, expire_timeout: i32) -> Message {
+    let text = Some(summary).into_iter()
+        .chain(Some(body).filter(|body| !body.is_empty()))
+        .collect::<Vec<_>>()
+        .join(": ")
+        .replace('\n', " ");
+
+    let duration = match expire_timeout {
+        -1 => DEFAULT_DURATION,
+        0 => MAX_DURATION,
+        millis => Duration::from_millis(millis.max(0) as u64).min(MAX_DURATION),
+    };
+
+    // notifications wait their turn rather than replacing each other,
+    // unless they're critical
+    let (priority, queue) = match urgency {
+        URGENCY_LOW => (-1, true),
+        URGENCY_CRITICAL => (1, false),
+        _ => (0, true),
+    };
+
+    Message {
+        kind: Some(kind(id)),
+        duration,
+        priority,
+        queue,
+        ..Message::new(text)
+    }
+}
+
+/// Serves notifications on the session bus until the connection is lost.
+/// Returns straight away if another notification daemon is running.
+pub async fn run(flash: flash::Sender) {
+    let server = Server { flash, last_id: 0 };
+
+    let connection = ConnectionBuilder::session()
+        .and_then(|builder| builder.serve_at(PATH, server));
+
+    let connection = match connection {
+        Ok(builder) => builder.build().await,
+        Err(e) => Err(e),
+    };
+
+    let connection = match connection {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("notifications: could not connect to the session bus: {:?}", e);
+            return;
+        }
+    };
+
+    // don't queue for the name or take it from a daemon that's running
+    match connection.request_name_with_flags(NAME, RequestNameFlags::DoNotQueue.into()).await {
+        Ok(_) => {}
+        Err(zbus::Error::NameTaken) => {
+            eprintln!("notifications: another notification daemon is running");
+            return;
+        }
+        Err(e) => {
+            eprintln!("notifications: could not own {}: {:?}", NAME, e);
+            return;
+        }
+    }
+
+    // requests are handled by the connection's own tasks for as long as
+    // it's alive
+    std::future::pending::<()>().await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let normal = message(3, "Build finished", "hstatus\nin 2s", URGENCY_NORMAL, -1);
+        assert_eq!(normal.text, "Build finished: hstatus in 2s");
+        assert_eq!(normal.kind.as_deref(), Some("notification 3"));
+        assert_eq!(normal.duration, DEFAULT_DURATION);
+        assert!(normal.queue);
+
+        let critical = message(4, "Battery low", "", URGENCY_CRITICAL, 0);
+        assert_eq!(critical.text, "Battery low");
+        assert_eq!(critical.duration, MAX_DURATION);
+        assert_eq!(critical.priority, 1);
+        assert!(!critical.queue);
+
+        assert_eq!(message(5, "Hi", "", URGENCY_LOW, 1500).duration, Duration::from_millis(1500));
+    }
+}
@@ -0,0 +1,105 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::status::Block;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One plain text line per update
+    Text,
+    /// The swaybar/i3bar JSON protocol, see swaybar-protocol(7)
+    I3bar,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "i3bar" => Ok(Format::I3bar),
+            _ => Err(format!("unknown output format: {} (expected text or i3bar)", s)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Header {
+    version: u32,
+    click_events: bool,
+}
+
+pub struct Output<W> {
+    format: Format,
+    writer: W,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(format: Format, writer: W) -> Self {
+        Output { format, writer }
+    }
+
+    pub fn header(&mut self) -> io::Result<()> {
+        match self.format {
+            Format::Text => Ok(()),
+            Format::I3bar => {
+                serde_json::to_writer(&mut self.writer, &Header { version: 1, click_events: true })?;
+                // open the infinite array of status lines
+                writeln!(self.writer, "\n[")?;
+                self.writer.flush()
+            }
+        }
+    }
+
+    pub fn line(&mut self, blocks: &[Block]) -> io::Result<()> {
+        match self.format {
+            Format::Text => {
+                writeln!(self.writer, "{}", text(blocks))?;
+            }
+            Format::I3bar => {
+                serde_json::to_writer(&mut self.writer, blocks)?;
+                writeln!(self.writer, ",")?;
+            }
+        }
+
+        self.writer.flush()
+    }
+}
+
+/// The line as shown in the text format
+pub fn text(blocks: &[Block]) -> String {
+    blocks.iter()
+        .map(|block| block.full_text.as_str())
+        .collect::<Vec<_>>()
+        .join("   ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn i3bar() {
+        let mut output = Output::new(Format::I3bar, Vec::new());
+        output.header().unwrap();
+        output.line(&[Block::text("flash", "hello".to_owned())]).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output.writer).unwrap(),
+            "{\"version\":1,\"click_events\":true}\n[\n[{\"name\":\"flash\",\"full_text\":\"hello\"}],\n");
+    }
+
+    #[test]
+    fn text() {
+        let mut output = Output::new(Format::Text, Vec::new());
+        output.header().unwrap();
+        output.line(&[
+            Block::text("a", "one".to_owned()),
+            Block::text("b", "two".to_owned()),
+        ]).unwrap();
+
+        assert_eq!(String::from_utf8(output.writer).unwrap(), "one   two\n");
+    }
+}
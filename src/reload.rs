@@ -0,0 +1,26 @@
+use std::path::Path;
+
+use futures::stream::{self, Stream, StreamExt};
+use inotify::WatchMask;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_stream::wrappers::SignalStream;
+
+use crate::util::watch;
+
+/// Requests to reload the config, sent on SIGHUP or when the config file
+/// at `path` is written
+pub fn triggers(path: Option<&Path>) -> impl Stream<Item = ()> {
+    let hangups = signal(SignalKind::hangup())
+        .map(SignalStream::new)
+        .map_err(|e| eprintln!("reload: could not handle SIGHUP: {:?}", e))
+        .ok();
+
+    let changes = path.and_then(|path| watch::file(path, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+        .map_err(|e| eprintln!("reload: could not watch {}: {:?}", path.display(), e))
+        .ok());
+
+    stream::select(
+        stream::iter(hangups).flatten(),
+        stream::iter(changes).flatten(),
+    )
+}
@@ -0,0 +1,705 @@
+use futures::{Stream, StreamExt};
+use futures::future::{self, Either};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tokio_stream::wrappers::IntervalStream;
+
+use crate::flash;
+use crate::status::Value;
+use crate::util::process;
+use crate::util::stream::dedup;
+use crate::util::uevent;
+
+mod upower;
+
+const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+/// How often to read batteries when we can't be told about changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often to read batteries in between uevents. Not every change in
+/// charge is announced, so the percentage and rate still need refreshing.
+const UEVENT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+const CHARGING_ICON: &str = "⚡";
+const FULL_ICON: &str = "🔌";
+
+/// Options of the battery source. Which keys are accepted depends on the
+/// backend, so that eg. `path` with `backend = "upower"` is an error.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "toml::value::Table")]
+pub struct Options {
+    pub alerts: AlertOptions,
+    pub backend: Backend,
+}
+
+#[derive(Deserialize)]
+struct RawOptions {
+    #[serde(flatten)]
+    alerts: AlertOptions,
+    // takes the keys left over, and rejects those its backend doesn't know
+    #[serde(flatten)]
+    backend: Backend,
+}
+
+impl TryFrom<toml::value::Table> for Options {
+    type Error = String;
+
+    fn try_from(mut options: toml::value::Table) -> Result<Self, Self::Error> {
+        // serde has no default for the tag of an enum
+        options.entry("backend").or_insert_with(|| "sysfs".into());
+
+        let raw: RawOptions = toml::Value::Table(options).try_into()
+            .map_err(|e| e.to_string())?;
+
+        raw.alerts.check()?;
+
+        Ok(Options { alerts: raw.alerts, backend: raw.backend })
+    }
+}
+
+/// When and how a discharging battery is alerted about, whatever the backend
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct AlertOptions {
+    /// Percentage at or below which a discharging battery is low
+    #[serde(default = "default_warn")]
+    pub warn: u8,
+
+    /// Percentage at or below which a discharging battery is critical
+    #[serde(default = "default_critical")]
+    pub critical: u8,
+
+    /// Percentage points the charge must rise back above a threshold
+    /// before crossing it again alerts again
+    #[serde(default = "default_hysteresis")]
+    pub hysteresis: u8,
+
+    #[serde(default = "default_warn_color")]
+    pub warn_color: String,
+
+    #[serde(default = "default_critical_color")]
+    pub critical_color: String,
+
+    /// Shell command run when the battery becomes critical, eg.
+    /// `systemctl suspend`
+    pub critical_command: Option<String>,
+}
+
+impl AlertOptions {
+    fn check(&self) -> Result<(), String> {
+        for (name, percent) in [("warn", self.warn), ("critical", self.critical)] {
+            if percent > 100 {
+                return Err(format!("{} is a percentage, but is {}", name, percent));
+            }
+        }
+
+        if self.critical > self.warn {
+            return Err(format!("critical ({}) is above warn ({})", self.critical, self.warn));
+        }
+
+        Ok(())
+    }
+}
+
+/// Where readings come from, chosen by the `backend` key
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum Backend {
+    /// Reads power supplies from sysfs, refreshed on uevents
+    Sysfs(SysfsOptions),
+    /// Asks UPower over D-Bus, which tells us about every change
+    Upower(UpowerOptions),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SysfsOptions {
+    /// Path to a power supply in sysfs, eg. `/sys/class/power_supply/BAT0`.
+    /// All system batteries are combined if omitted.
+    pub path: Option<PathBuf>,
+
+    /// Also show the percentage of each battery when combining batteries
+    #[serde(default)]
+    pub breakdown: bool,
+
+    /// Number of seconds the (dis)charge rate is averaged over when
+    /// estimating the time remaining
+    #[serde(default = "default_rate_window")]
+    pub rate_window: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpowerOptions {
+    /// Also show the batteries of peripherals such as mice and headsets
+    #[serde(default)]
+    pub peripherals: bool,
+}
+
+fn default_rate_window() -> u64 {
+    300
+}
+
+fn default_warn() -> u8 {
+    15
+}
+
+fn default_critical() -> u8 {
+    5
+}
+
+fn default_hysteresis() -> u8 {
+    2
+}
+
+fn default_warn_color() -> String {
+    "#ffaa00".to_owned()
+}
+
+fn default_critical_color() -> String {
+    "#ff0000".to_owned()
+}
+
+pub fn from_options(options: &Options, flash: flash::Sender) -> impl Stream<Item = Option<Value>> {
+    let readings = match &options.backend {
+        Backend::Sysfs(sysfs_options) => Either::Left(sysfs(sysfs_options)),
+        Backend::Upower(upower_options) => Either::Right(upower::upower(upower_options.peripherals)),
+    };
+
+    let mut alerts = Alerts::new(&options.alerts);
+    let options = options.alerts.clone();
+
+    dedup(readings.map(move |reading| {
+        let (total, text) = reading?;
+
+        match alerts.update(&total) {
+            Some(Alert::Warn) => {
+                let _ = flash.send(format!("Battery low: {}", text).into());
+            }
+            Some(Alert::Critical) => {
+                let _ = flash.send(format!("Battery critical: {}", text).into());
+
+                if let Some(command) = &options.critical_command {
+                    process::spawn(command);
+                }
+            }
+            Some(Alert::Normal) | None => {}
+        }
+
+        let color = match alerts.current() {
+            Alert::Normal => None,
+            Alert::Warn => Some(options.warn_color.clone()),
+            Alert::Critical => Some(options.critical_color.clone()),
+        };
+
+        Some(Value { text, color, urgent: alerts.current() == Alert::Critical })
+    }))
+}
+
+/// The combined reading of the batteries in sysfs along with its text
+fn sysfs(options: &SysfsOptions) -> impl Stream<Item = Option<(Reading, String)>> {
+    let readings = match &options.path {
+        Some(path) => Either::Left(battery(path)),
+        None => Either::Right(batteries()),
+    };
+
+    let mut estimate = Estimate::new(Duration::from_secs(options.rate_window));
+    let breakdown = options.breakdown;
+
+    readings.map(move |readings| {
+        let total = Reading::combine(&readings)?;
+        let remaining = estimate.update(&total, Instant::now());
+        let breakdown = Some(readings.as_slice()).filter(|readings| breakdown && readings.len() > 1);
+        let text = format(&total, remaining, breakdown);
+
+        Some((total, text))
+    })
+}
+
+/// Yields whenever power supplies should be read: on power_supply uevents
+/// while we can listen for them, otherwise every `POLL_INTERVAL`
+fn updates() -> impl Stream<Item = ()> {
+    match uevent::listen() {
+        Ok(socket) => {
+            let events = uevent::events(socket)
+                .filter(|event| future::ready(event.subsystem() == Some("power_supply")))
+                .map(|_| ());
+
+            // the events stop if the socket fails, leaving only polling
+            let events = events.chain(futures::stream::once(future::ready(()))
+                .flat_map(|()| {
+                    eprintln!("source::battery: lost uevents, polling instead");
+                    poll(POLL_INTERVAL)
+                }));
+
+            Either::Left(futures::stream::select(poll(UEVENT_POLL_INTERVAL), events))
+        }
+        Err(e) => {
+            eprintln!("source::battery: can't listen for uevents, polling instead: {:?}", e);
+            Either::Right(poll(POLL_INTERVAL))
+        }
+    }
+}
+
+fn poll(period: Duration) -> impl Stream<Item = ()> {
+    IntervalStream::new(tokio::time::interval(period)).map(|_| ())
+}
+
+/// Readings of every system battery, rescanned each time so that batteries
+/// being inserted or removed are noticed
+pub fn batteries() -> impl Stream<Item = Vec<Reading>> {
+    updates()
+        .then(|()| async {
+            read_batteries().await
+                .map_err(|e| eprintln!("source::battery: {:?}", e))
+                .unwrap_or_default()
+        })
+}
+
+async fn read_batteries() -> Result<Vec<Reading>, io::Error> {
+    let mut entries = tokio::fs::read_dir(POWER_SUPPLY_PATH).await?;
+    let mut batteries = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        // the power supply may have gone away since listing the directory
+        if let Ok(uevent) = tokio::fs::read_to_string(entry.path().join("uevent")).await {
+            batteries.push((entry.file_name(), uevent));
+        }
+    }
+
+    batteries.sort();
+
+    Ok(batteries.iter()
+        .map(|(_, uevent)| properties(uevent))
+        .filter(is_system_battery)
+        .filter_map(|props| Reading::from_properties(&props))
+        .collect())
+}
+
+/// Readings of the battery at `path`
+pub fn battery(path: &Path) -> impl Stream<Item = Vec<Reading>> {
+    // the uevent file has every attribute of the power supply, read in one go
+    let path = path.join("uevent");
+
+    updates()
+        .then(move |()| tokio::fs::read_to_string(path.clone()))
+        .map(|uevent| uevent.ok()
+            .and_then(|uevent| Reading::from_properties(&properties(&uevent)))
+            .into_iter()
+            .collect())
+}
+
+fn format(reading: &Reading, remaining: Option<Duration>, breakdown: Option<&[Reading]>) -> String {
+    let icon = match reading.status {
+        Status::Charging => Some(CHARGING_ICON.to_owned()),
+        Status::Full | Status::NotCharging => Some(FULL_ICON.to_owned()),
+        Status::Discharging | Status::Unknown => None,
+    };
+
+    let percent = format!("{}%", reading.percent.round());
+
+    let remaining = remaining.map(|remaining| {
+        let minutes = remaining.as_secs() / 60;
+        format!("{}:{:02}", minutes / 60, minutes % 60)
+    });
+
+    let breakdown = breakdown.map(|readings| {
+        let percents = readings.iter()
+            .map(|reading| format!("{}%", reading.percent.round()))
+            .collect::<Vec<_>>();
+
+        format!("({})", percents.join(" "))
+    });
+
+    icon.into_iter()
+        .chain(Some(percent))
+        .chain(remaining)
+        .chain(breakdown)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses the contents of a power supply's uevent file, or the properties
+/// of a power_supply uevent
+pub fn properties(uevent: &str) -> HashMap<&str, &str> {
+    uevent.lines()
+        .filter_map(|line| line.trim().split_once('='))
+        .filter_map(|(key, value)| Some((key.strip_prefix("POWER_SUPPLY_")?, value)))
+        .collect()
+}
+
+/// Whether the power supply powers the system, as opposed to eg. AC
+/// adapters or the battery of a wireless mouse
+pub fn is_system_battery(props: &HashMap<&str, &str>) -> bool {
+    props.get("TYPE") == Some(&"Battery") && props.get("SCOPE") != Some(&"Device")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Charging,
+    Discharging,
+    Full,
+    NotCharging,
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reading {
+    pub status: Status,
+    pub percent: f64,
+    /// Remaining and full capacity. None if it only reports capacity.
+    pub level: Option<Level>,
+    /// Rate of charge or discharge in the same unit as `level` per hour
+    pub rate: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Level {
+    pub now: f64,
+    pub full: f64,
+    pub unit: Unit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    /// µWh, from energy_*
+    Energy,
+    /// µAh, from charge_*
+    Charge,
+}
+
+impl Reading {
+    pub fn from_properties(props: &HashMap<&str, &str>) -> Option<Reading> {
+        if props.get("PRESENT") == Some(&"0") {
+            return None;
+        }
+
+        let number = |key: &str| -> Option<f64> {
+            props.get(key)?.parse().ok()
+        };
+
+        let status = match props.get("STATUS").copied() {
+            Some("Charging") => Status::Charging,
+            Some("Discharging") => Status::Discharging,
+            Some("Full") => Status::Full,
+            Some("Not charging") => Status::NotCharging,
+            _ => Status::Unknown,
+        };
+
+        let voltage = number("VOLTAGE_NOW").filter(|voltage| *voltage > 0.0);
+
+        let level = |unit, now, full| Some(Level { now: number(now)?, full: number(full)?, unit });
+
+        let energy = level(Unit::Energy, "ENERGY_NOW", "ENERGY_FULL");
+        let charge = level(Unit::Charge, "CHARGE_NOW", "CHARGE_FULL");
+        let power = number("POWER_NOW").map(f64::abs);
+        let current = number("CURRENT_NOW").map(f64::abs);
+
+        // voltage is in µV, so power (µW) = current (µA) * voltage / 10^6
+        let (level, rate) = match (energy, charge) {
+            (Some(energy), _) => (Some(energy), power.or_else(|| Some(current? * voltage? / 1e6))),
+            (None, Some(charge)) => (Some(charge), current.or_else(|| Some(power? * 1e6 / voltage?))),
+            (None, None) => (None, None),
+        };
+
+        let percent = level
+            .and_then(|level| level.percent())
+            .or_else(|| number("CAPACITY"))?;
+
+        Some(Reading {
+            status,
+            percent: percent.clamp(0.0, 100.0),
+            level,
+            rate: rate.filter(|rate| *rate > 0.0),
+        })
+    }
+
+    /// Combines the readings of several batteries as if they were one
+    pub fn combine(readings: &[Reading]) -> Option<Reading> {
+        let status = |status| readings.iter().any(|reading| reading.status == status);
+
+        let status = if status(Status::Charging) {
+            Status::Charging
+        } else if status(Status::Discharging) {
+            Status::Discharging
+        } else if !readings.is_empty() && readings.iter().all(|reading| matches!(reading.status, Status::Full | Status::NotCharging)) {
+            Status::Full
+        } else {
+            Status::Unknown
+        };
+
+        // capacities can only be summed if every battery reports the same unit
+        let levels = readings.iter()
+            .map(|reading| reading.level)
+            .collect::<Option<Vec<_>>>()
+            .filter(|levels| levels.windows(2).all(|pair| pair[0].unit == pair[1].unit));
+
+        let level = levels.and_then(|levels| Some(Level {
+            now: levels.iter().map(|level| level.now).sum(),
+            full: levels.iter().map(|level| level.full).sum(),
+            unit: levels.first()?.unit,
+        }));
+
+        let rate = match level {
+            Some(_) => readings.iter()
+                .filter_map(|reading| reading.rate)
+                .reduce(|a, b| a + b),
+            None => None,
+        };
+
+        let percent = match level.and_then(|level| level.percent()) {
+            Some(percent) => percent,
+            None if readings.is_empty() => return None,
+            None => readings.iter().map(|reading| reading.percent).sum::<f64>() / readings.len() as f64,
+        };
+
+        Some(Reading { status, percent, level, rate })
+    }
+}
+
+impl Level {
+    fn percent(&self) -> Option<f64> {
+        Some(self.now * 100.0 / self.full).filter(|_| self.full > 0.0)
+    }
+}
+
+/// Estimates time to empty or full from the rate of charge averaged over a
+/// window of time, so the estimate doesn't jump around
+pub struct Estimate {
+    window: Duration,
+    status: Status,
+    rates: VecDeque<(Instant, f64)>,
+}
+
+impl Estimate {
+    pub fn new(window: Duration) -> Self {
+        Estimate { window, status: Status::Unknown, rates: VecDeque::new() }
+    }
+
+    pub fn update(&mut self, reading: &Reading, now: Instant) -> Option<Duration> {
+        if reading.status != self.status {
+            self.status = reading.status;
+            self.rates.clear();
+        }
+
+        while matches!(self.rates.front(), Some((time, _)) if now.duration_since(*time) > self.window) {
+            self.rates.pop_front();
+        }
+
+        if let Some(rate) = reading.rate {
+            self.rates.push_back((now, rate));
+        }
+
+        if self.rates.is_empty() {
+            return None;
+        }
+
+        let rate = self.rates.iter().map(|(_, rate)| rate).sum::<f64>() / self.rates.len() as f64;
+        let level = reading.level?;
+
+        let hours = match reading.status {
+            Status::Discharging => level.now / rate,
+            Status::Charging => (level.full - level.now).max(0.0) / rate,
+            _ => return None,
+        };
+
+        Some(Duration::from_secs_f64(hours * 3600.0))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Alert {
+    Normal,
+    Warn,
+    Critical,
+}
+
+/// Tracks which threshold a discharging battery is below, with hysteresis
+/// so that hovering around a threshold doesn't alert over and over.
+/// Plugging in doesn't clear an alert by itself, so that a brief replug or
+/// a flaky status reading doesn't lead to alerting again.
+pub struct Alerts {
+    warn: f64,
+    critical: f64,
+    hysteresis: f64,
+    current: Alert,
+}
+
+impl Alerts {
+    pub fn new(options: &AlertOptions) -> Self {
+        Alerts {
+            warn: options.warn.into(),
+            critical: options.critical.into(),
+            hysteresis: options.hysteresis.into(),
+            current: Alert::Normal,
+        }
+    }
+
+    pub fn current(&self) -> Alert {
+        self.current
+    }
+
+    /// Returns the new alert if the battery crossed into a more severe one
+    pub fn update(&mut self, reading: &Reading) -> Option<Alert> {
+        let previous = self.current;
+
+        // an alert stays active until the charge rises past its threshold
+        // plus the hysteresis, and only a discharging battery raises one
+        let below = |threshold: f64, alert| match previous >= alert {
+            true => reading.percent <= threshold + self.hysteresis,
+            false => reading.status == Status::Discharging && reading.percent <= threshold,
+        };
+
+        self.current = if below(self.critical, Alert::Critical) {
+            Alert::Critical
+        } else if below(self.warn, Alert::Warn) {
+            Alert::Warn
+        } else {
+            Alert::Normal
+        };
+
+        Some(self.current).filter(|current| *current > previous)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(300);
+
+    fn parse(uevent: &str) -> Option<Reading> {
+        Reading::from_properties(&properties(uevent))
+    }
+
+    #[test]
+    fn energy() {
+        let reading = parse("\
+            POWER_SUPPLY_NAME=BAT0\n\
+            POWER_SUPPLY_STATUS=Discharging\n\
+            POWER_SUPPLY_POWER_NOW=10000000\n\
+            POWER_SUPPLY_ENERGY_FULL=50000000\n\
+            POWER_SUPPLY_ENERGY_NOW=25000000\n\
+            POWER_SUPPLY_CAPACITY=49\n").unwrap();
+
+        assert_eq!(reading.status, Status::Discharging);
+        assert_eq!(reading.percent, 50.0);
+
+        let mut estimate = Estimate::new(WINDOW);
+        assert_eq!(estimate.update(&reading, Instant::now()), Some(Duration::from_secs(9000)));
+        assert_eq!(format(&reading, Some(Duration::from_secs(9000)), None), "50% 2:30");
+    }
+
+    #[test]
+    fn charge_with_power() {
+        let reading = parse("\
+            POWER_SUPPLY_STATUS=Charging\n\
+            POWER_SUPPLY_VOLTAGE_NOW=10000000\n\
+            POWER_SUPPLY_POWER_NOW=20000000\n\
+            POWER_SUPPLY_CHARGE_FULL=4000000\n\
+            POWER_SUPPLY_CHARGE_NOW=3000000\n").unwrap();
+
+        assert_eq!(reading.rate, Some(2000000.0));
+
+        let remaining = Estimate::new(WINDOW).update(&reading, Instant::now());
+        assert_eq!(remaining, Some(Duration::from_secs(1800)));
+        assert_eq!(format(&reading, remaining, None), "⚡ 75% 0:30");
+    }
+
+    #[test]
+    fn capacity_only() {
+        let reading = parse("POWER_SUPPLY_STATUS=Full\nPOWER_SUPPLY_CAPACITY=100\n").unwrap();
+        assert_eq!(Estimate::new(WINDOW).update(&reading, Instant::now()), None);
+        assert_eq!(format(&reading, None, None), "🔌 100%");
+
+        assert_eq!(parse("POWER_SUPPLY_STATUS=Full\n"), None);
+    }
+
+    #[test]
+    fn smoothing() {
+        let reading = |power| parse(&format!("\
+            POWER_SUPPLY_STATUS=Discharging\n\
+            POWER_SUPPLY_POWER_NOW={}\n\
+            POWER_SUPPLY_ENERGY_FULL=50000000\n\
+            POWER_SUPPLY_ENERGY_NOW=20000000\n", power)).unwrap();
+
+        let start = Instant::now();
+        let mut estimate = Estimate::new(Duration::from_secs(60));
+        estimate.update(&reading(30000000), start);
+        estimate.update(&reading(10000000), start + Duration::from_secs(30));
+        assert_eq!(estimate.update(&reading(10000000), start + Duration::from_secs(61)), Some(Duration::from_secs(7200)));
+    }
+
+    #[test]
+    fn combine() {
+        let internal = parse("\
+            POWER_SUPPLY_STATUS=Unknown\n\
+            POWER_SUPPLY_ENERGY_FULL=20000000\n\
+            POWER_SUPPLY_ENERGY_NOW=20000000\n").unwrap();
+
+        let external = parse("\
+            POWER_SUPPLY_STATUS=Discharging\n\
+            POWER_SUPPLY_POWER_NOW=10000000\n\
+            POWER_SUPPLY_ENERGY_FULL=60000000\n\
+            POWER_SUPPLY_ENERGY_NOW=30000000\n").unwrap();
+
+        let readings = [internal, external];
+        let total = Reading::combine(&readings).unwrap();
+
+        assert_eq!(total.status, Status::Discharging);
+        assert_eq!(total.percent, 62.5);
+        assert_eq!(total.rate, Some(10000000.0));
+
+        let remaining = Estimate::new(WINDOW).update(&total, Instant::now());
+        assert_eq!(format(&total, remaining, Some(&readings)), "63% 5:00 (100% 50%)");
+
+        assert_eq!(Reading::combine(&[]), None);
+    }
+
+    #[test]
+    fn system_battery() {
+        assert!(is_system_battery(&properties("POWER_SUPPLY_TYPE=Battery\nPOWER_SUPPLY_SCOPE=System\n")));
+        assert!(!is_system_battery(&properties("POWER_SUPPLY_TYPE=Battery\nPOWER_SUPPLY_SCOPE=Device\n")));
+        assert!(!is_system_battery(&properties("POWER_SUPPLY_TYPE=Mains\n")));
+        assert_eq!(parse("POWER_SUPPLY_PRESENT=0\nPOWER_SUPPLY_CAPACITY=0\n"), None);
+    }
+
+    #[test]
+    fn alerts() {
+        let options: Options = toml::from_str("").unwrap();
+        let mut alerts = Alerts::new(&options.alerts);
+
+        let reading = |status, percent| Reading { status, percent, level: None, rate: None };
+
+        assert_eq!(alerts.update(&reading(Status::Discharging, 20.0)), None);
+        assert_eq!(alerts.update(&reading(Status::Discharging, 15.0)), Some(Alert::Warn));
+        assert_eq!(alerts.update(&reading(Status::Discharging, 16.0)), None);
+        assert_eq!(alerts.current(), Alert::Warn);
+        assert_eq!(alerts.update(&reading(Status::Discharging, 15.0)), None);
+        assert_eq!(alerts.update(&reading(Status::Discharging, 4.0)), Some(Alert::Critical));
+        assert_eq!(alerts.update(&reading(Status::Discharging, 8.0)), None);
+        assert_eq!(alerts.current(), Alert::Warn);
+
+        // replugging or a reading of another status keeps the alert
+        assert_eq!(alerts.update(&reading(Status::Charging, 8.0)), None);
+        assert_eq!(alerts.current(), Alert::Warn);
+        assert_eq!(alerts.update(&reading(Status::Unknown, 8.0)), None);
+        assert_eq!(alerts.update(&reading(Status::Discharging, 8.0)), None);
+        assert_eq!(alerts.current(), Alert::Warn);
+
+        // until charging takes it past the threshold and the hysteresis
+        assert_eq!(alerts.update(&reading(Status::Charging, 20.0)), None);
+        assert_eq!(alerts.current(), Alert::Normal);
+        assert_eq!(alerts.update(&reading(Status::Discharging, 15.0)), Some(Alert::Warn));
+
+        // a battery that isn't discharging doesn't raise one
+        let mut alerts = Alerts::new(&options.alerts);
+        assert_eq!(alerts.update(&reading(Status::NotCharging, 4.0)), None);
+        assert_eq!(alerts.current(), Alert::Normal);
+        assert_eq!(alerts.update(&reading(Status::Discharging, 4.0)), Some(Alert::Critical));
+    }
+}
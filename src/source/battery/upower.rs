@@ -0,0 +1,356 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{self, Either, FutureExt};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use zbus::dbus_proxy;
+use zbus::zvariant::OwnedObjectPath;
+
+use super::{format, Reading, Status};
+use crate::util;
+use crate::util::backoff;
+
+#[dbus_proxy(
+    interface = "org.freedesktop.UPower",
+    default_service = "org.freedesktop.UPower",
+    default_path = "/org/freedesktop/UPower",
+)]
+trait UPower {
+    fn enumerate_devices(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    /// A composite device UPower keeps up to date with the combined state of
+    /// the system's batteries
+    fn get_display_device(&self) -> zbus::Result<OwnedObjectPath>;
+
+    #[dbus_proxy(signal)]
+    fn device_added(&self, device: OwnedObjectPath) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn device_removed(&self, device: OwnedObjectPath) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.UPower.Device",
+    default_service = "org.freedesktop.UPower",
+)]
+trait Device {
+    #[dbus_proxy(property, name = "Type")]
+    fn kind(&self) -> zbus::Result<u32>;
+
+    #[dbus_proxy(property)]
+    fn power_supply(&self) -> zbus::Result<bool>;
+
+    #[dbus_proxy(property)]
+    fn is_present(&self) -> zbus::Result<bool>;
+
+    #[dbus_proxy(property)]
+    fn state(&self) -> zbus::Result<u32>;
+
+    #[dbus_proxy(property)]
+    fn percentage(&self) -> zbus::Result<f64>;
+
+    /// Seconds, or 0 if unknown
+    #[dbus_proxy(property)]
+    fn time_to_empty(&self) -> zbus::Result<i64>;
+
+    /// Seconds, or 0 if unknown
+    #[dbus_proxy(property)]
+    fn time_to_full(&self) -> zbus::Result<i64>;
+}
+
+/// Values of the `Type` property we care about, see the UPower docs for
+/// the full list
+mod kind {
+    pub const LINE_POWER: u32 = 1;
+    pub const MOUSE: u32 = 5;
+    pub const KEYBOARD: u32 = 6;
+    pub const PHONE: u32 = 8;
+    pub const TABLET: u32 = 10;
+    pub const GAMING_INPUT: u32 = 12;
+    pub const HEADSET: u32 = 17;
+    pub const HEADPHONES: u32 = 19;
+}
+
+/// The properties of a device as of one update
+#[derive(Clone, Debug, PartialEq)]
+struct Properties {
+    kind: u32,
+    is_present: bool,
+    state: u32,
+    percentage: f64,
+    time_to_empty: i64,
+    time_to_full: i64,
+}
+
+struct Devices {
+    display: DeviceProxy<'static>,
+    peripherals: Vec<DeviceProxy<'static>>,
+}
+
+/// The reading of UPower's display device along with its text, followed by
+/// the percentage of each peripheral if `peripherals` is set. Updated
+/// whenever UPower announces a change, without polling. Tries again with
+/// backoff until the system bus and UPower can be reached, as the bar
+/// often starts before them.
+pub fn upower(peripherals: bool) -> impl Stream<Item = Option<(Reading, String)>> {
+    let sessions = backoff::reconnect(move || async move {
+        connect(peripherals).await
+            .map_err(|e| eprintln!("source::battery::upower: can't reach UPower: {:?}", e))
+            .ok()
+    });
+
+    sessions.flat_map(|devices| follow(devices, changes))
+}
+
+/// The devices to show, once UPower has answered with them
+async fn connect(peripherals: bool) -> zbus::Result<impl Stream<Item = zbus::Result<Devices>>> {
+    let upower = upower_proxy().await?;
+    let mut devices = devices(upower, peripherals).await?.boxed();
+
+    // building proxies doesn't go over the bus, so this is the first we
+    // hear of whether UPower is there
+    let first = devices.try_next().await?;
+
+    Ok(stream::iter(first.map(Ok)).chain(devices))
+}
+
+/// The readings of the latest devices. Without peripherals the devices are
+/// only enumerated once, so their readings outlive `devices`.
+fn follow<D, Fut, S>(devices: impl Stream<Item = zbus::Result<D>>, changes: fn(D) -> Fut)
+    -> impl Stream<Item = Option<(Reading, String)>>
+    where Fut: Future<Output = S>,
+          S: Stream<Item = zbus::Result<Option<(Reading, String)>>>,
+{
+    let readings = devices
+        .and_then(move |devices| changes(devices).map(Ok))
+        .map(util::stream::flatten_result_stream);
+
+    util::stream::follow_latest(readings)
+        .map(|result| result
+            .map_err(|e| eprintln!("source::battery::upower: {:?}", e))
+            .ok()
+            .flatten())
+}
+
+async fn upower_proxy() -> zbus::Result<UPowerProxy<'static>> {
+    let dbus = zbus::Connection::system().await?;
+
+    UPowerProxy::builder(&dbus)
+        .build()
+        .await
+}
+
+/// The devices to show, enumerated again whenever a device is added or
+/// removed if we're showing peripherals
+async fn devices(upower: UPowerProxy<'static>, peripherals: bool)
+    -> zbus::Result<impl Stream<Item = zbus::Result<Devices>>>
+{
+    let changes = if peripherals {
+        let added = upower.receive_device_added().await?.map(|_| ());
+        let removed = upower.receive_device_removed().await?.map(|_| ());
+        Either::Left(stream::select(added, removed))
+    } else {
+        Either::Right(stream::empty())
+    };
+
+    Ok(stream::once(future::ready(()))
+        .chain(changes)
+        .then(move |()| {
+            let upower = upower.clone();
+            async move { enumerate(&upower, peripherals).await }
+        }))
+}
+
+async fn enumerate(upower: &UPowerProxy<'static>, peripherals: bool) -> zbus::Result<Devices> {
+    let dbus = upower.connection();
+
+    let display = device(dbus, upower.get_display_device().await?).await?;
+
+    let mut devices = Vec::new();
+
+    if peripherals {
+        for path in upower.enumerate_devices().await? {
+            let device = device(dbus, path).await?;
+
+            // system batteries are already part of the display device
+            if !device.power_supply().await? && device.kind().await? != kind::LINE_POWER {
+                devices.push(device);
+            }
+        }
+    }
+
+    Ok(Devices { display, peripherals: devices })
+}
+
+async fn device(dbus: &zbus::Connection, path: OwnedObjectPath) -> zbus::Result<DeviceProxy<'static>> {
+    DeviceProxy::builder(dbus)
+        .path(path)?
+        .build()
+        .await
+}
+
+/// Reads the devices each time one of their properties changes. The
+/// proxies cache properties and keep them up to date from the change
+/// signals, so reading them doesn't go over the bus.
+async fn changes(devices: Devices) -> impl Stream<Item = zbus::Result<Option<(Reading, String)>>> {
+    let mut changes = Vec::new();
+
+    for device in std::iter::once(&devices.display).chain(&devices.peripherals) {
+        changes.push(device.receive_is_present_changed().await.map(|_| ()).boxed());
+        changes.push(device.receive_state_changed().await.map(|_| ()).boxed());
+        changes.push(device.receive_percentage_changed().await.map(|_| ()).boxed());
+    }
+
+    changes.push(devices.display.receive_time_to_empty_changed().await.map(|_| ()).boxed());
+    changes.push(devices.display.receive_time_to_full_changed().await.map(|_| ()).boxed());
+
+    let devices = Arc::new(devices);
+
+    stream::once(future::ready(()))
+        .chain(stream::select_all(changes))
+        .then(move |()| {
+            let devices = devices.clone();
+            async move { read(&devices).await }
+        })
+}
+
+async fn read(devices: &Devices) -> zbus::Result<Option<(Reading, String)>> {
+    let display = properties(&devices.display).await?;
+
+    let mut peripherals = Vec::new();
+
+    for device in &devices.peripherals {
+        peripherals.push(properties(device).await?);
+    }
+
+    Ok(text(&display, &peripherals))
+}
+
+async fn properties(device: &DeviceProxy<'_>) -> zbus::Result<Properties> {
+    Ok(Properties {
+        kind: device.kind().await?,
+        is_present: device.is_present().await?,
+        state: device.state().await?,
+        percentage: device.percentage().await?,
+        time_to_empty: device.time_to_empty().await?,
+        time_to_full: device.time_to_full().await?,
+    })
+}
+
+fn text(display: &Properties, peripherals: &[Properties]) -> Option<(Reading, String)> {
+    if !display.is_present {
+        return None;
+    }
+
+    let reading = reading(display);
+
+    let remaining = match reading.status {
+        Status::Charging => display.time_to_full,
+        Status::Discharging => display.time_to_empty,
+        _ => 0,
+    };
+
+    let remaining = Some(remaining)
+        .filter(|remaining| *remaining > 0)
+        .map(|remaining| Duration::from_secs(remaining as u64));
+
+    let peripherals = peripherals.iter()
+        .filter(|device| device.is_present)
+        .map(|device| format!("{} {}%", icon(device.kind), device.percentage.round()));
+
+    let text = Some(format(&reading, remaining, None))
+        .into_iter()
+        .chain(peripherals)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    Some((reading, text))
+}
+
+fn reading(device: &Properties) -> Reading {
+    // see the State property in the UPower docs. Pending charge is plugged
+    // in but held off charging, eg. by a charge threshold.
+    let status = match device.state {
+        1 => Status::Charging,
+        2 | 3 | 6 => Status::Discharging,
+        4 => Status::Full,
+        5 => Status::NotCharging,
+        _ => Status::Unknown,
+    };
+
+    Reading {
+        status,
+        percent: device.percentage.clamp(0.0, 100.0),
+        level: None,
+        rate: None,
+    }
+}
+
+fn icon(kind: u32) -> &'static str {
+    match kind {
+        kind::MOUSE => "🖱",
+        kind::KEYBOARD => "⌨",
+        kind::PHONE => "📱",
+        kind::TABLET => "📟",
+        kind::GAMING_INPUT => "🎮",
+        kind::HEADSET | kind::HEADPHONES => "🎧",
+        _ => "🔋",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn later(texts: Vec<&'static str>) -> impl Stream<Item = zbus::Result<Option<(Reading, String)>>> {
+        stream::iter(texts).then(|text| async move {
+            tokio::task::yield_now().await;
+            let reading = Reading { status: Status::Full, percent: 100.0, level: None, rate: None };
+            Ok(Some((reading, text.to_owned())))
+        })
+    }
+
+    #[tokio::test]
+    async fn readings_outlive_devices() {
+        // enumerated once, as without peripherals
+        let devices = stream::once(future::ready(Ok(vec!["100%", "99%"])));
+
+        let texts = follow(devices, later)
+            .map(|reading| reading.unwrap().1)
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(texts, ["100%", "99%"]);
+    }
+
+    #[test]
+    fn display_and_peripherals() {
+        let display = Properties {
+            kind: 2,
+            is_present: true,
+            state: 2,
+            percentage: 42.4,
+            time_to_empty: 5400,
+            time_to_full: 0,
+        };
+
+        let mouse = Properties { kind: kind::MOUSE, percentage: 80.0, time_to_empty: 0, ..display.clone() };
+        let gone = Properties { kind: kind::HEADSET, is_present: false, ..mouse.clone() };
+
+        let (reading, shown) = text(&display, &[mouse, gone]).unwrap();
+        assert_eq!(reading.status, Status::Discharging);
+        assert_eq!(shown, "42% 1:30 🖱 80%");
+
+        let charging = Properties { state: 1, time_to_full: 600, ..display.clone() };
+        assert_eq!(text(&charging, &[]).unwrap().1, "⚡ 42% 0:10");
+
+        let pending = Properties { state: 5, time_to_full: 600, ..display.clone() };
+        let (reading, shown) = text(&pending, &[]).unwrap();
+        assert_eq!(reading.status, Status::NotCharging);
+        assert_eq!(shown, "🔌 42%");
+
+        let missing = Properties { is_present: false, ..display };
+        assert_eq!(text(&missing, &[]), None);
+    }
+}
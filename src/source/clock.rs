@@ -1,17 +1,249 @@
+use std::path::Path;
 use std::time::Duration;
 
-use chrono::Local;
-use futures::{Stream, stream};
+use chrono::format::{Fixed, Item, Numeric, StrftimeItems};
+use chrono::{DateTime, Local, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use futures::{Stream, StreamExt, stream};
+use futures::stream::BoxStream;
+use inotify::WatchMask;
+use serde::Deserialize;
 
-pub fn clock() -> impl Stream<Item = Option<String>> {
-    stream::unfold(Duration::from_secs(0), |delay| async move {
-        tokio::time::sleep(delay).await;
+use crate::util::{realtime, watch};
 
-        let time = Local::now();
-        let formatted = time.format("%d %a %H:%M:%S").to_string();
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Options {
+    /// strftime(3) style format, or a list of formats the `cycle` message
+    /// switches between
+    #[serde(default)]
+    pub format: Formats,
 
-        let delay = Duration::from_micros((1_000_001 - time.timestamp_subsec_micros()) as u64);
+    /// IANA time zone, eg. `Europe/Berlin`. Local time if omitted.
+    pub timezone: Option<Tz>,
+}
+
+/// One or more valid formats
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "FormatsRepr")]
+pub struct Formats(Vec<String>);
+
+impl Default for Formats {
+    fn default() -> Self {
+        Formats(vec!["%d %a %H:%M:%S".to_owned(), "%H:%M:%S".to_owned()])
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FormatsRepr {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl TryFrom<FormatsRepr> for Formats {
+    type Error = String;
+
+    fn try_from(repr: FormatsRepr) -> Result<Self, Self::Error> {
+        let formats = match repr {
+            FormatsRepr::One(format) => vec![format],
+            FormatsRepr::Many(formats) => formats,
+        };
+
+        if formats.is_empty() {
+            return Err("format list is empty".to_owned());
+        }
+
+        // chrono panics when formatting with an invalid format, so catch
+        // them while loading the config
+        for format in &formats {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(format!("invalid format: {:?}", format));
+            }
+        }
+
+        Ok(Formats(formats))
+    }
+}
+
+/// Added to fallback sleeps so that we wake up after the time changes
+/// rather than just before it
+const FALLBACK_SLACK: Duration = Duration::from_millis(1);
 
-        Some((Some(formatted), delay))
+const FALLBACK_MAX_SLEEP: Duration = Duration::from_secs(1);
+
+/// Where the local time zone is configured. It's replaced rather than
+/// written when the time zone changes.
+const LOCALTIME: &str = "/etc/localtime";
+
+/// Shows the time in the configured time zone. The `cycle` message
+/// switches to the next format.
+pub fn clock(options: &Options, messages: impl Stream<Item = String> + Unpin) -> impl Stream<Item = Option<String>> {
+    let timer = realtime::timer()
+        .map_err(|e| eprintln!("source::clock: can't notice clock changes: {:?}", e))
+        .ok();
+
+    let zone_changes = watch::file(Path::new(LOCALTIME), WatchMask::CREATE | WatchMask::MOVED_TO)
+        .map_err(|e| eprintln!("source::clock: can't notice time zone changes: {:?}", e))
+        .ok();
+
+    let clock = Clock {
+        formats: options.format.0.clone(),
+        timezone: options.timezone,
+        mode: 0,
+        wakeup: None,
+        messages,
+        timer,
+        zone_changes: stream::iter(zone_changes).flatten().boxed(),
+    };
+
+    stream::unfold(clock, |mut clock| async move {
+        clock.wait().await;
+        Some((Some(clock.render()), clock))
     })
 }
+
+struct Clock<M> {
+    formats: Vec<String>,
+    timezone: Option<Tz>,
+    mode: usize,
+    /// When the displayed time next changes, None to render straight away
+    wakeup: Option<DateTime<Utc>>,
+    messages: M,
+    timer: Option<realtime::Timer>,
+    zone_changes: BoxStream<'static, ()>,
+}
+
+impl<M: Stream<Item = String> + Unpin> Clock<M> {
+    /// Waits until the clock needs rendering again
+    async fn wait(&mut self) {
+        let Some(wakeup) = self.wakeup else { return };
+
+        tokio::select! {
+            _ = sleep_until(self.timer.as_ref(), wakeup) => {}
+            Some(()) = self.zone_changes.next() => {}
+            Some(message) = self.messages.next() => match message.as_str() {
+                "cycle" => self.mode = (self.mode + 1) % self.formats.len(),
+                _ => eprintln!("source::clock: unknown message: {:?}", message),
+            },
+        }
+    }
+
+    fn render(&mut self) -> String {
+        let format = &self.formats[self.mode];
+        let time = Utc::now();
+
+        self.wakeup = Some(next_change(time, granularity(format), self.timezone));
+
+        self::format(time, format, self.timezone)
+    }
+}
+
+async fn sleep_until(timer: Option<&realtime::Timer>, wakeup: DateTime<Utc>) {
+    if let Some(timer) = timer {
+        match timer.sleep_until(wakeup.into()).await {
+            Ok(()) => return,
+            Err(e) => eprintln!("source::clock: timer failed: {:?}", e),
+        }
+    }
+
+    // tokio's clock doesn't count time spent suspended, so wake up at least
+    // every second to stay roughly right after a resume
+    let delay = (wakeup - Utc::now()).to_std().unwrap_or_default() + FALLBACK_SLACK;
+    tokio::time::sleep(delay.min(FALLBACK_MAX_SLEEP)).await;
+}
+
+/// How often a format's output can change, in seconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Granularity {
+    Second = 1,
+    Minute = 60,
+    /// Formats without a time still change on the hour, as that's when
+    /// daylight saving time starts and ends
+    Hour = 3600,
+}
+
+fn granularity(format: &str) -> Granularity {
+    StrftimeItems::new(format)
+        .map(|item| match item {
+            Item::Numeric(Numeric::Second | Numeric::Nanosecond | Numeric::Timestamp, _) |
+            Item::Fixed(Fixed::Nanosecond | Fixed::Nanosecond3 | Fixed::Nanosecond6 | Fixed::Nanosecond9 |
+                Fixed::RFC2822 | Fixed::RFC3339) => Granularity::Second,
+            Item::Numeric(Numeric::Minute, _) => Granularity::Minute,
+            _ => Granularity::Hour,
+        })
+        .min()
+        .unwrap_or(Granularity::Hour)
+}
+
+/// The next time the output of a format with the given granularity changes
+fn next_change(time: DateTime<Utc>, granularity: Granularity, timezone: Option<Tz>) -> DateTime<Utc> {
+    // hours start on the hour of local time, which isn't the hour of UTC in
+    // time zones offset by eg. 5:30
+    let offset = match timezone {
+        Some(timezone) => time.with_timezone(&timezone).offset().fix().local_minus_utc(),
+        None => time.with_timezone(&Local).offset().local_minus_utc(),
+    } as i64;
+
+    let unit = granularity as i64;
+    let next = ((time.timestamp() + offset).div_euclid(unit) + 1) * unit - offset;
+
+    Utc.timestamp_opt(next, 0).unwrap()
+}
+
+fn format(time: DateTime<Utc>, format: &str, timezone: Option<Tz>) -> String {
+    match timezone {
+        Some(timezone) => time.with_timezone(&timezone).format(format).to_string(),
+        None => time.with_timezone(&Local).format(format).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timezones() {
+        let time = Utc.with_ymd_and_hms(2023, 1, 2, 12, 30, 0).unwrap();
+
+        assert_eq!(format(time, "%H:%M %Z", Some(Tz::UTC)), "12:30 UTC");
+        assert_eq!(format(time, "%H:%M %Z", Some(Tz::Europe__Berlin)), "13:30 CET");
+        assert_eq!(format(time, "week %V", Some(Tz::Asia__Tokyo)), "week 01");
+    }
+
+    #[test]
+    fn granularities() {
+        assert_eq!(granularity("%d %a %H:%M:%S"), Granularity::Second);
+        assert_eq!(granularity("%T"), Granularity::Second);
+        assert_eq!(granularity("%H:%M"), Granularity::Minute);
+        assert_eq!(granularity("%I %p"), Granularity::Hour);
+        assert_eq!(granularity("week %V"), Granularity::Hour);
+    }
+
+    #[test]
+    fn next_changes() {
+        let time = Utc.with_ymd_and_hms(2023, 1, 2, 12, 30, 15).unwrap();
+        let at = |h, m, s| Utc.with_ymd_and_hms(2023, 1, 2, h, m, s).unwrap();
+
+        assert_eq!(next_change(time, Granularity::Second, Some(Tz::UTC)), at(12, 30, 16));
+        assert_eq!(next_change(time, Granularity::Minute, Some(Tz::UTC)), at(12, 31, 0));
+        assert_eq!(next_change(time, Granularity::Hour, Some(Tz::UTC)), at(13, 0, 0));
+        // 18:00:15 in India, the next hour starts at 19:00 or 13:30 UTC
+        assert_eq!(next_change(time, Granularity::Hour, Some(Tz::Asia__Kolkata)), at(13, 30, 0));
+    }
+
+    #[test]
+    fn options() {
+        let options: Options = toml::from_str(r#"
+            format = ["%H:%M", "%Y-%m-%d"]
+            timezone = "America/New_York"
+        "#).unwrap();
+
+        assert_eq!(options.format, Formats(vec!["%H:%M".to_owned(), "%Y-%m-%d".to_owned()]));
+        assert_eq!(options.timezone, Some(Tz::America__New_York));
+
+        assert!(toml::from_str::<Options>(r#"format = "%H:%"#).is_err());
+        assert!(toml::from_str::<Options>("format = []").is_err());
+        assert!(toml::from_str::<Options>(r#"timezone = "Mars/Olympus_Mons""#).is_err());
+    }
+}
@@ -1,3 +1,147 @@
 pub mod battery;
 pub mod clock;
 pub mod wifi;
+
+use std::collections::HashMap;
+
+use futures::{Stream, StreamExt};
+use futures::stream::BoxStream;
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::{UnboundedReceiverStream, WatchStream};
+
+use crate::flash;
+use crate::status::{Messages, Value};
+use crate::util::future::{self, Task};
+
+/// A source along with its source-specific options, as declared in a
+/// segment of the config file
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Config {
+    Battery(battery::Options),
+    Clock(clock::Options),
+    NetworkManager(wifi::networkmanager::Options),
+    WpaSupplicant(wifi::wpa_supplicant::Options),
+}
+
+impl Config {
+    /// Whether the source handles messages sent by `Action::Message`
+    pub fn accepts_messages(&self) -> bool {
+        matches!(self, Config::Clock(_) | Config::NetworkManager(_))
+    }
+
+    pub fn stream(&self, messages: Messages, flash: flash::Sender) -> BoxStream<'static, Option<Value>> {
+        match self {
+            Config::Battery(options) => battery::from_options(options, flash).boxed(),
+            Config::Clock(options) => text(clock::clock(options, messages)).boxed(),
+            Config::NetworkManager(options) => text(wifi::networkmanager::network(options, messages)).boxed(),
+            Config::WpaSupplicant(options) => text(wifi::wpa_supplicant::ssid(&options.control_path)).boxed(),
+        }
+    }
+}
+
+fn text(stream: impl Stream<Item = Option<String>>) -> impl Stream<Item = Option<Value>> {
+    stream.map(|text| text.map(Value::from))
+}
+
+/// The segment a source runs for, along with its config, so that segments
+/// with the same options still get a source each
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub name: String,
+    pub instance: Option<String>,
+    pub config: Config,
+}
+
+/// Runs each source in its own task so that it keeps its state, eg. D-Bus
+/// subscriptions, when the config is reloaded without changing it.
+pub struct Pool {
+    running: HashMap<Key, Running>,
+    flash: flash::Sender,
+}
+
+struct Running {
+    values: watch::Receiver<Option<Value>>,
+    messages: mpsc::UnboundedSender<String>,
+    _task: Task,
+}
+
+impl Pool {
+    pub fn new(flash: flash::Sender) -> Self {
+        Pool { running: HashMap::new(), flash }
+    }
+
+    /// Returns the values of the source, starting it if it isn't running
+    /// already, along with the sender for its messages.
+    pub fn get(&mut self, key: &Key) -> (WatchStream<Option<Value>>, mpsc::UnboundedSender<String>) {
+        let flash = &self.flash;
+
+        let running = self.running.entry(key.clone())
+            .or_insert_with(|| {
+                let (tx, rx) = watch::channel(None);
+                let (messages_tx, messages_rx) = mpsc::unbounded_channel();
+                let mut stream = key.config.stream(UnboundedReceiverStream::new(messages_rx), flash.clone());
+
+                let task = future::spawn(async move {
+                    while let Some(value) = stream.next().await {
+                        if tx.send(value).is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                Running { values: rx, messages: messages_tx, _task: task }
+            });
+
+        (WatchStream::new(running.values.clone()), running.messages.clone())
+    }
+
+    /// Stops the source, so that it starts afresh the next time it's asked
+    /// for
+    pub fn stop(&mut self, key: &Key) {
+        self.running.remove(key);
+    }
+
+    /// Stops all sources not in `keys`
+    pub fn retain(&mut self, keys: impl IntoIterator<Item = Key>) {
+        let keys = keys.into_iter().collect::<Vec<_>>();
+        self.running.retain(|key, _| keys.contains(key));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn pool_reuses_running_sources() {
+        let mut pool = Pool::new(mpsc::unbounded_channel().0);
+        let config = Config::Clock(clock::Options { format: Default::default(), timezone: None });
+        let clock = Key { name: "clock".to_owned(), instance: None, config };
+
+        let (_, first) = pool.get(&clock);
+        let (_, second) = pool.get(&clock);
+        assert!(first.same_channel(&second));
+
+        pool.retain([]);
+
+        let (_, third) = pool.get(&clock);
+        assert!(!first.same_channel(&third));
+    }
+
+    #[tokio::test]
+    async fn pool_separates_segments() {
+        let mut pool = Pool::new(mpsc::unbounded_channel().0);
+        let config = Config::Clock(clock::Options { format: Default::default(), timezone: None });
+        let home = Key { name: "clock".to_owned(), instance: Some("home".to_owned()), config };
+        let away = Key { instance: Some("away".to_owned()), ..home.clone() };
+
+        let (_, first) = pool.get(&home);
+        let (_, second) = pool.get(&away);
+        assert!(!first.same_channel(&second));
+
+        // restarting one leaves the other running
+        pool.stop(&home);
+        let (_, third) = pool.get(&away);
+        assert!(second.same_channel(&third));
+    }
+}
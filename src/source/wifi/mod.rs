@@ -1,4 +1,3 @@
-#[allow(unused)]
 pub mod wpa_supplicant;
 
 pub mod networkmanager;
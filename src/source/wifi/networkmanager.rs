@@ -1,9 +1,17 @@
-use futures::future::{self, FutureExt, TryFutureExt};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use futures::future::{self, Either, FutureExt, TryFutureExt};
 use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use serde::Deserialize;
 use zbus::dbus_proxy;
-use zbus::zvariant::OwnedObjectPath;
+use zbus::fdo::DBusProxy;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
 
 use crate::util;
+use crate::util::backoff;
+use crate::util::rtnetlink;
 
 #[dbus_proxy(
     interface = "org.freedesktop.NetworkManager",
@@ -13,6 +21,9 @@ use crate::util;
 trait NetworkManager {
     #[dbus_proxy(property)]
     fn primary_connection(&self) -> zbus::Result<OwnedObjectPath>;
+
+    #[dbus_proxy(property)]
+    fn active_connections(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
 }
 
 #[dbus_proxy(
@@ -22,60 +33,830 @@ trait NetworkManager {
 trait ActiveConnection {
     #[dbus_proxy(property)]
     fn id(&self) -> zbus::Result<String>;
+
+    /// The type of the connection's settings, eg. `802-11-wireless`
+    #[dbus_proxy(property, name = "Type")]
+    fn kind(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn state(&self) -> zbus::Result<u32>;
+
+    #[dbus_proxy(property)]
+    fn devices(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[dbus_proxy(property)]
+    fn ip4_config(&self) -> zbus::Result<OwnedObjectPath>;
+
+    #[dbus_proxy(property)]
+    fn ip6_config(&self) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager.IP4Config",
+    default_service = "org.freedesktop.NetworkManager",
+)]
+trait Ip4Config {
+    /// Each address as a dict with `address` and `prefix`
+    #[dbus_proxy(property)]
+    fn address_data(&self) -> zbus::Result<Vec<HashMap<String, OwnedValue>>>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager.IP6Config",
+    default_service = "org.freedesktop.NetworkManager",
+)]
+trait Ip6Config {
+    /// Each address as a dict with `address` and `prefix`
+    #[dbus_proxy(property)]
+    fn address_data(&self) -> zbus::Result<Vec<HashMap<String, OwnedValue>>>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager.Device.Wireless",
+    default_service = "org.freedesktop.NetworkManager",
+)]
+trait Wireless {
+    #[dbus_proxy(property)]
+    fn active_access_point(&self) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager.AccessPoint",
+    default_service = "org.freedesktop.NetworkManager",
+)]
+trait AccessPoint {
+    #[dbus_proxy(property)]
+    fn ssid(&self) -> zbus::Result<Vec<u8>>;
+
+    /// Percent
+    #[dbus_proxy(property)]
+    fn strength(&self) -> zbus::Result<u8>;
+
+    /// MHz
+    #[dbus_proxy(property)]
+    fn frequency(&self) -> zbus::Result<u32>;
 }
 
-pub fn network() -> impl Stream<Item = Option<String>> {
+/// Values of the `Type` property of active connections we tell apart
+mod kind {
+    pub const ETHERNET: &str = "802-3-ethernet";
+    pub const WIRELESS: &str = "802-11-wireless";
+    pub const VPN: &str = "vpn";
+    pub const WIREGUARD: &str = "wireguard";
+    pub const GSM: &str = "gsm";
+    pub const CDMA: &str = "cdma";
+    pub const BLUETOOTH: &str = "bluetooth";
+}
+
+/// Values of the `State` property of active connections
+mod state {
+    pub const ACTIVATING: u32 = 1;
+    pub const ACTIVATED: u32 = 2;
+    pub const DEACTIVATING: u32 = 3;
+    pub const DEACTIVATED: u32 = 4;
+}
+
+/// The sorts of connections, each with its own icon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Ethernet,
+    Wifi,
+    /// Including WireGuard
+    Vpn,
+    Mobile,
+    Bluetooth,
+    Other,
+}
+
+impl Kind {
+    fn of(kind: &str) -> Self {
+        match kind {
+            kind::ETHERNET => Kind::Ethernet,
+            kind::WIRELESS => Kind::Wifi,
+            kind::VPN | kind::WIREGUARD => Kind::Vpn,
+            kind::GSM | kind::CDMA => Kind::Mobile,
+            kind::BLUETOOTH => Kind::Bluetooth,
+            _ => Kind::Other,
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            Kind::Ethernet => "🖧",
+            Kind::Wifi => "📶",
+            Kind::Vpn => "🔒",
+            Kind::Mobile => "📱",
+            Kind::Bluetooth => "ᛒ",
+            Kind::Other => "🌐",
+        }
+    }
+}
+
+/// The properties of an active connection as of one update
+#[derive(Clone, Debug, PartialEq)]
+struct Connection {
+    path: OwnedObjectPath,
+    id: String,
+    kind: Kind,
+    state: u32,
+    access_point: Option<Station>,
+    addresses: Vec<IpAddr>,
+}
+
+/// The properties of a connection shown as a badge, which are all that's
+/// read of it
+#[derive(Clone, Debug, PartialEq)]
+struct Badge {
+    path: OwnedObjectPath,
+    id: String,
+    kind: Kind,
+    state: u32,
+}
+
+/// What the segment shows as of one update
+#[derive(Clone, Debug, PartialEq)]
+enum Status {
+    /// The primary connection, None while there isn't one, and the others
+    /// shown as badges
+    Connections(Option<Connection>, Vec<Badge>),
+    /// The addresses the kernel has while NetworkManager isn't running
+    Addresses(Vec<IpAddr>),
+}
+
+/// The access point a wireless connection is associated with
+#[derive(Clone, Debug, PartialEq)]
+struct Station {
+    ssid: String,
+    strength: u8,
+    frequency: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Options {
+    /// Kinds of connections shown as badges after the primary connection
+    /// while they're up, eg. VPNs
+    #[serde(default = "default_badges")]
+    pub badges: Vec<Kind>,
+
+    /// Show the addresses of the primary connection, toggled by the
+    /// `addresses` message
+    #[serde(default)]
+    pub addresses: bool,
+}
+
+fn default_badges() -> Vec<Kind> {
+    vec![Kind::Vpn]
+}
+
+const NAME: &str = "org.freedesktop.NetworkManager";
+
+/// The primary connection with an icon for its type, and the signal
+/// strength of wireless connections, followed by badges for the other
+/// connections of the kinds in `options.badges`. Follows NetworkManager
+/// when it restarts, and connects to the system bus again if it's lost.
+///
+/// The `addresses` message toggles showing the addresses of the primary
+/// connection. While they're shown and NetworkManager isn't running, or
+/// the system bus can't be reached, the kernel's addresses are shown
+/// instead.
+pub fn network(options: &Options, messages: impl Stream<Item = String>) -> impl Stream<Item = Option<String>> {
+    let badges = options.badges.clone();
+
+    let statuses = system_bus()
+        .flat_map(move |dbus| statuses(dbus, badges.clone())
+            // NetworkManager is out of reach until we're back on the bus
+            .chain(stream::once(future::ready(None))));
+
+    let addresses = messages.scan(options.addresses, |addresses, message| {
+        match message.as_str() {
+            "addresses" => *addresses = !*addresses,
+            _ => eprintln!("source::wifi::networkmanager: unknown message: {:?}", message),
+        }
+
+        future::ready(Some(*addresses))
+    });
+
+    let addresses = stream::once(future::ready(options.addresses)).chain(addresses);
+
+    let texts = util::stream::combine(statuses, addresses)
+        .map(|(status, addresses)| match (status.flatten(), addresses.unwrap_or_default()) {
+            (Some(status), addresses) => Either::Left(stream::once(future::ready(text(&status, addresses)))),
+            // only follow the kernel while there's something to show
+            (None, true) => Either::Right(Either::Left(kernel_addresses()
+                .map(|status| text(&status?, true)))),
+            (None, false) => Either::Right(Either::Right(stream::once(future::ready(None)))),
+        });
+
+    let texts = util::stream::follow_latest(texts);
+
+    // a property changing doesn't always change the text
+    util::stream::dedup(texts)
+}
+
+/// Connections to the system bus, each made once the last is lost
+fn system_bus() -> impl Stream<Item = zbus::Connection> {
+    backoff::reconnect(|| async {
+        zbus::Connection::system().await
+            .map_err(|e| eprintln!("source::wifi::networkmanager: can't connect to the system bus: {:?}", e))
+            .ok()
+    })
+}
+
+/// The connections for as long as we're connected to `dbus`, starting over
+/// each time NetworkManager starts, and None while it isn't running
+fn statuses(dbus: zbus::Connection, badges: Vec<Kind>) -> impl Stream<Item = Option<Status>> {
+    let runs = util::stream::from_future(
+        running(dbus.clone()).map(util::stream::flatten_result_stream));
+
+    let statuses = runs.map(move |running| match log(running) {
+        Some(true) => Either::Left(connections(dbus.clone(), badges.clone()).map(Some)),
+        _ => Either::Right(stream::once(future::ready(None))),
+    });
+
+    // the runs end with the connection to the bus, and so should we
+    let statuses = statuses.map(|statuses| Some(statuses.boxed()))
+        .chain(stream::once(future::ready(None)))
+        .map(|statuses| statuses.unwrap_or_else(|| stream::empty().boxed()));
+
+    util::stream::follow_latest(statuses)
+}
+
+fn log<T>(result: zbus::Result<T>) -> Option<T> {
+    result
+        .map_err(|e| eprintln!("source::wifi::networkmanager: {:?}", e))
+        .ok()
+}
+
+fn connections(dbus: zbus::Connection, badges: Vec<Kind>) -> impl Stream<Item = Status> {
+    let primary = primary(dbus.clone()).map(log);
+
+    // with no badges there's nothing to follow besides the primary
+    let active = match badges.is_empty() {
+        true => Either::Left(stream::empty()),
+        false => Either::Right(active(dbus, badges)),
+    };
+
+    util::stream::combine(primary, active)
+        .filter_map(|(primary, active)| future::ready({
+            primary.flatten().map(|primary| {
+                let others = active.unwrap_or_default().into_iter()
+                    .filter(|badge| Some(&badge.path) != primary.as_ref().map(|primary| &primary.path))
+                    .filter(|badge| badge.state == state::ACTIVATED)
+                    .collect();
+
+                Status::Connections(primary, others)
+            })
+        }))
+}
+
+/// The addresses of all interfaces, read again each time one is added or
+/// removed
+fn kernel_addresses() -> impl Stream<Item = Option<Status>> {
+    let changes = rtnetlink::listen()
+        .map_err(|e| eprintln!("source::wifi::networkmanager: can't follow addresses: {:?}", e))
+        .ok()
+        .map(rtnetlink::changes);
+
+    stream::once(future::ready(()))
+        .chain(stream::iter(changes).flatten())
+        .then(|()| rtnetlink::addresses())
+        .map(|addresses| addresses
+            .map_err(|e| eprintln!("source::wifi::networkmanager: can't read addresses: {:?}", e))
+            .ok()
+            .map(Status::Addresses))
+}
+
+/// Whether NetworkManager is running, then each time it starts or stops
+async fn running(dbus: zbus::Connection) -> zbus::Result<impl Stream<Item = zbus::Result<bool>>> {
+    let proxy = DBusProxy::new(&dbus).await?;
+
+    // subscribe before asking so that we don't miss a change in between
+    let changes = proxy.receive_name_owner_changed_with_args(&[(0, NAME)]).await?;
+    let running = proxy.name_has_owner(NAME.try_into()?).await?;
+
+    let changes = changes.map(|change| Ok(change.args()?.new_owner().is_some()));
+
+    Ok(stream::once(future::ready(Ok(running))).chain(changes))
+}
+
+/// The primary connection, None while there isn't one
+fn primary(dbus: zbus::Connection) -> impl Stream<Item = zbus::Result<Option<Connection>>> {
     let primary_conn_stream = util::stream::from_future(
-        network_manager()
+        network_manager(dbus)
             .and_then(|nm| primary_connection(nm).map(Ok))
             .map(util::stream::flatten_result_stream));
 
-    let primary_name_stream = primary_conn_stream
-        .and_then(|conn| connection_name(conn).map(Ok))
+    let connections = primary_conn_stream
+        .map_ok(|conn| match conn {
+            Some(conn) => Either::Left(connection(conn)),
+            None => Either::Right(stream::once(future::ready(Ok(None)))),
+        })
         .map(util::stream::flatten_result_stream);
 
-    util::stream::follow_latest(primary_name_stream)
-        .map(|result| result
-            .map_err(|e| eprintln!("source::wifi::networkmanager: {:?}", e))
-            .ok())
+    util::stream::follow_latest(connections)
 }
 
-async fn network_manager() -> zbus::Result<NetworkManagerProxy<'static>> {
-    let dbus = zbus::Connection::system().await?;
+/// The active connections of the kinds in `badges`, which may include the
+/// primary one. Those that can't be read are left out.
+fn active(dbus: zbus::Connection, badges: Vec<Kind>) -> impl Stream<Item = Vec<Badge>> {
+    let lists = util::stream::from_future(
+        network_manager(dbus)
+            .and_then(|nm| active_connections(nm).map(Ok))
+            .map(util::stream::flatten_result_stream));
+
+    let connections = lists.map(move |conns| match log(conns) {
+        Some(conns) if !conns.is_empty() => {
+            let badges = badges.clone();
+            let conns = conns.into_iter()
+                .map(move |conn| util::stream::from_future(
+                        badge(conn, badges.clone()).map(util::stream::flatten_result_stream))
+                    .map(|badge| log(badge).flatten())
+                    .boxed());
+
+            Either::Left(util::stream::combine_all(conns)
+                .map(|conns| conns.into_iter().flatten().flatten().collect()))
+        }
+        _ => Either::Right(stream::once(future::ready(Vec::new()))),
+    });
 
-    let nm = NetworkManagerProxy::builder(&dbus)
+    util::stream::follow_latest(connections)
+}
+
+async fn network_manager(dbus: zbus::Connection) -> zbus::Result<NetworkManagerProxy<'static>> {
+    NetworkManagerProxy::builder(&dbus)
         .build()
-        .await?;
+        .await
+}
+
+/// Builds a proxy for each object `paths` points to, None while it points
+/// to `/`, which NetworkManager uses for a missing object
+fn proxies<P>(
+    dbus: zbus::Connection,
+    paths: impl Stream<Item = zbus::Result<OwnedObjectPath>>,
+    builder: fn(&zbus::Connection) -> zbus::ProxyBuilder<'static, P>,
+) -> impl Stream<Item = zbus::Result<Option<P>>>
+    where P: From<zbus::Proxy<'static>>
+{
+    paths.and_then(move |path| {
+        let builder = builder(&dbus);
+        async move {
+            if path.as_str() == "/" {
+                return Ok(None);
+            }
 
-    Ok(nm)
+            builder.path(path)?.build().await.map(Some)
+        }
+    })
 }
 
-async fn primary_connection(proxy: NetworkManagerProxy<'_>) -> impl Stream<Item = zbus::Result<ActiveConnectionProxy<'_>>> + '_ {
+/// The primary connection, or None when there's no connection
+async fn primary_connection(proxy: NetworkManagerProxy<'static>)
+    -> impl Stream<Item = zbus::Result<Option<ActiveConnectionProxy<'static>>>>
+{
     let stream = proxy.receive_primary_connection_changed().await
         .then(|change| async move { change.get().await });
 
-    let dbus = proxy.connection().clone();
     let conn = proxy.primary_connection().await;
 
-    stream::once(future::ready(conn))
+    proxies(proxy.connection().clone(), stream::once(future::ready(conn)).chain(stream), ActiveConnectionProxy::builder)
+}
+
+async fn active_connections(proxy: NetworkManagerProxy<'static>)
+    -> impl Stream<Item = zbus::Result<Vec<ActiveConnectionProxy<'static>>>>
+{
+    let stream = proxy.receive_active_connections_changed().await
+        .then(|change| async move { change.get().await });
+
+    let dbus = proxy.connection().clone();
+    let conns = proxy.active_connections().await;
+
+    stream::once(future::ready(conns))
         .chain(stream)
-        .and_then(move |path| {
+        .and_then(move |paths| {
             let dbus = dbus.clone();
             async move {
-                Ok(ActiveConnectionProxy::builder(&dbus)
-                    .path(path)?
-                    .build()
-                    .await?)
+                let mut conns = Vec::new();
+
+                for path in paths {
+                    conns.push(ActiveConnectionProxy::builder(&dbus)
+                        .path(path)?
+                        .build()
+                        .await?);
+                }
+
+                Ok(conns)
             }
         })
 }
 
-async fn connection_name(proxy: ActiveConnectionProxy<'_>) -> impl Stream<Item = zbus::Result<String>> + '_ {
-    let stream = proxy.receive_id_changed().await
+/// The objects besides the connection itself that its properties are read
+/// from, as of one update
+#[derive(Clone, Default)]
+struct Objects {
+    access_point: Option<AccessPointProxy<'static>>,
+    ip4: Option<Ip4ConfigProxy<'static>>,
+    ip6: Option<Ip6ConfigProxy<'static>>,
+}
+
+/// The connection's properties each time one of them, or one of its
+/// objects', changes. Objects that can't be followed are left out.
+fn connection(conn: ActiveConnectionProxy<'static>) -> impl Stream<Item = zbus::Result<Option<Connection>>> {
+    let stations = util::stream::from_future(
+        access_points(conn.clone()).map(util::stream::flatten_result_stream));
+
+    let ip4 = util::stream::from_future(ip4_configs(conn.clone()));
+    let ip6 = util::stream::from_future(ip6_configs(conn.clone()));
+
+    let objects = util::stream::combine(
+        util::stream::combine(stations.map(|station| log(station).flatten()), ip4.map(|ip4| log(ip4).flatten())),
+        ip6.map(|ip6| log(ip6).flatten()),
+    );
+
+    let changes = objects.map(move |(first, ip6)| {
+        let (access_point, ip4) = first.unwrap_or_default();
+
+        let objects = Objects {
+            access_point: access_point.flatten(),
+            ip4: ip4.flatten(),
+            ip6: ip6.flatten(),
+        };
+
+        util::stream::from_future(changes(conn.clone(), objects))
+    });
+
+    util::stream::follow_latest(changes)
+}
+
+/// The connection each time its name or state changes, always None if it
+/// isn't one of the kinds in `badges`. Unlike `connection`, this doesn't
+/// follow its access point or addresses, which a badge doesn't show.
+async fn badge(conn: ActiveConnectionProxy<'static>, badges: Vec<Kind>)
+    -> zbus::Result<impl Stream<Item = zbus::Result<Option<Badge>>>>
+{
+    let kind = Kind::of(&conn.kind().await?);
+
+    if !badges.contains(&kind) {
+        return Ok(Either::Left(stream::once(future::ready(Ok(None)))));
+    }
+
+    let changes = stream::select(
+        conn.receive_id_changed().await.map(|_| ()),
+        conn.receive_state_changed().await.map(|_| ()),
+    );
+
+    let badges = stream::once(future::ready(()))
+        .chain(changes)
+        .then(move |()| {
+            let conn = conn.clone();
+            async move {
+                Ok(Some(Badge {
+                    path: conn.path().to_owned().into(),
+                    id: conn.id().await?,
+                    kind,
+                    state: conn.state().await?,
+                }))
+            }
+        });
+
+    Ok(Either::Right(badges))
+}
+
+/// The access point of a wireless connection's device as it roams, always
+/// None for other connections
+async fn access_points(conn: ActiveConnectionProxy<'static>)
+    -> zbus::Result<impl Stream<Item = zbus::Result<Option<AccessPointProxy<'static>>>>>
+{
+    if conn.kind().await? != kind::WIRELESS {
+        return Ok(Either::Left(stream::once(future::ready(Ok(None)))));
+    }
+
+    let stream = conn.receive_devices_changed().await
         .then(|change| async move { change.get().await });
 
-    let name = proxy.id().await;
+    let dbus = conn.connection().clone();
+    let devices = conn.devices().await;
 
-    stream::once(future::ready(name))
+    // a wireless connection has the one device
+    let access_points = stream::once(future::ready(devices))
         .chain(stream)
+        .map_ok(move |devices| match devices.into_iter().next() {
+            Some(device) => Either::Left(util::stream::from_future(
+                active_access_point(dbus.clone(), device).map(util::stream::flatten_result_stream))),
+            None => Either::Right(stream::once(future::ready(Ok(None)))),
+        })
+        .map(util::stream::flatten_result_stream);
+
+    Ok(Either::Right(util::stream::follow_latest(access_points)))
+}
+
+async fn active_access_point(dbus: zbus::Connection, device: OwnedObjectPath)
+    -> zbus::Result<impl Stream<Item = zbus::Result<Option<AccessPointProxy<'static>>>>>
+{
+    let wireless = WirelessProxy::builder(&dbus)
+        .path(device)?
+        .build()
+        .await?;
+
+    let stream = wireless.receive_active_access_point_changed().await
+        .then(|change| async move { change.get().await });
+
+    let access_point = wireless.active_access_point().await;
+
+    Ok(proxies(dbus, stream::once(future::ready(access_point)).chain(stream), AccessPointProxy::builder))
+}
+
+/// The connection's IPv4 configuration, replaced as it's renewed
+async fn ip4_configs(conn: ActiveConnectionProxy<'static>)
+    -> impl Stream<Item = zbus::Result<Option<Ip4ConfigProxy<'static>>>>
+{
+    let stream = conn.receive_ip4_config_changed().await
+        .then(|change| async move { change.get().await });
+
+    let config = conn.ip4_config().await;
+
+    proxies(conn.connection().clone(), stream::once(future::ready(config)).chain(stream), Ip4ConfigProxy::builder)
+}
+
+async fn ip6_configs(conn: ActiveConnectionProxy<'static>)
+    -> impl Stream<Item = zbus::Result<Option<Ip6ConfigProxy<'static>>>>
+{
+    let stream = conn.receive_ip6_config_changed().await
+        .then(|change| async move { change.get().await });
+
+    let config = conn.ip6_config().await;
+
+    proxies(conn.connection().clone(), stream::once(future::ready(config)).chain(stream), Ip6ConfigProxy::builder)
+}
+
+/// Reads the connection and its objects each time one of their properties
+/// changes. The proxies cache properties and keep them up to date from the
+/// change signals, so reading them doesn't go over the bus.
+async fn changes(conn: ActiveConnectionProxy<'static>, objects: Objects)
+    -> impl Stream<Item = zbus::Result<Option<Connection>>>
+{
+    let mut changes = vec![
+        conn.receive_id_changed().await.map(|_| ()).boxed(),
+        conn.receive_state_changed().await.map(|_| ()).boxed(),
+    ];
+
+    if let Some(access_point) = &objects.access_point {
+        changes.push(access_point.receive_ssid_changed().await.map(|_| ()).boxed());
+        changes.push(access_point.receive_strength_changed().await.map(|_| ()).boxed());
+        changes.push(access_point.receive_frequency_changed().await.map(|_| ()).boxed());
+    }
+
+    if let Some(ip4) = &objects.ip4 {
+        changes.push(ip4.receive_address_data_changed().await.map(|_| ()).boxed());
+    }
+
+    if let Some(ip6) = &objects.ip6 {
+        changes.push(ip6.receive_address_data_changed().await.map(|_| ()).boxed());
+    }
+
+    let proxies = Arc::new((conn, objects));
+
+    stream::once(future::ready(()))
+        .chain(stream::select_all(changes))
+        .then(move |()| {
+            let proxies = proxies.clone();
+            async move {
+                let (conn, objects) = &*proxies;
+                read(conn, objects).await.map(Some)
+            }
+        })
+}
+
+async fn read(conn: &ActiveConnectionProxy<'_>, objects: &Objects) -> zbus::Result<Connection> {
+    let access_point = match &objects.access_point {
+        Some(access_point) => Some(Station {
+            ssid: String::from_utf8_lossy(&access_point.ssid().await?).into_owned(),
+            strength: access_point.strength().await?,
+            frequency: access_point.frequency().await?,
+        }),
+        None => None,
+    };
+
+    let mut address_data = Vec::new();
+
+    if let Some(ip4) = &objects.ip4 {
+        address_data.extend(ip4.address_data().await?);
+    }
+
+    if let Some(ip6) = &objects.ip6 {
+        address_data.extend(ip6.address_data().await?);
+    }
+
+    Ok(Connection {
+        path: conn.path().to_owned().into(),
+        id: conn.id().await?,
+        kind: Kind::of(&conn.kind().await?),
+        state: conn.state().await?,
+        access_point,
+        addresses: addresses(&address_data),
+    })
+}
+
+/// The addresses in the `AddressData` of IP configurations, leaving out
+/// link-local addresses as the kernel fallback does
+fn addresses(address_data: &[HashMap<String, OwnedValue>]) -> Vec<IpAddr> {
+    address_data.iter()
+        .filter_map(|data| data.get("address")?.downcast_ref::<str>()?.parse().ok())
+        .filter(|address| match address {
+            IpAddr::V4(address) => !address.is_link_local(),
+            IpAddr::V6(address) => !address.is_unicast_link_local(),
+        })
+        .collect()
+}
+
+fn text(status: &Status, addresses: bool) -> Option<String> {
+    match status {
+        Status::Connections(primary, others) => Some(connections_text(primary.as_ref(), others, addresses)),
+        Status::Addresses(shown) if addresses => match shown.is_empty() {
+            true => Some("disconnected".to_owned()),
+            false => Some(format!("{} {}", Kind::Other.icon(), join(shown))),
+        },
+        Status::Addresses(_) => None,
+    }
+}
+
+fn join(addresses: &[IpAddr]) -> String {
+    addresses.iter()
+        .map(IpAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn connections_text(primary: Option<&Connection>, others: &[Badge], addresses: bool) -> String {
+    let addresses = primary
+        .filter(|primary| addresses && primary.state == state::ACTIVATED && !primary.addresses.is_empty())
+        .map(|primary| join(&primary.addresses));
+
+    let badges = others.iter()
+        .map(|badge| format!("{} {}", badge.kind.icon(), badge.id));
+
+    Some(primary_text(primary))
+        .into_iter()
+        .chain(addresses)
+        .chain(badges)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn primary_text(connection: Option<&Connection>) -> String {
+    let Some(connection) = connection else {
+        return "disconnected".to_owned();
+    };
+
+    let icon = connection.kind.icon();
+
+    match connection.state {
+        state::ACTIVATING => return format!("{} connecting", icon),
+        state::DEACTIVATING | state::DEACTIVATED => return "disconnected".to_owned(),
+        _ => {}
+    }
+
+    let Some(station) = &connection.access_point else {
+        return format!("{} {}", icon, connection.id);
+    };
+
+    // hidden networks don't broadcast their SSID
+    let name = match station.ssid.is_empty() {
+        true => &connection.id,
+        false => &station.ssid,
+    };
+
+    Some(format!("{} {} {}", icon, name, signal(station.strength)))
+        .into_iter()
+        .chain(band(station.frequency).map(str::to_owned))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A bar as tall as the signal is strong
+fn signal(strength: u8) -> char {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let level = (strength.min(100) as usize * (BARS.len() - 1) + 50) / 100;
+    BARS[level]
+}
+
+fn band(frequency: u32) -> Option<&'static str> {
+    match frequency {
+        2400..=2500 => Some("2.4GHz"),
+        4900..=5900 => Some("5GHz"),
+        5925..=7125 => Some("6GHz"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path(path: &str) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(path).unwrap()
+    }
+
+    #[test]
+    fn texts() {
+        let wired = Connection {
+            path: path("/1"),
+            id: "Wired connection 1".to_owned(),
+            kind: Kind::of(kind::ETHERNET),
+            state: state::ACTIVATED,
+            access_point: None,
+            addresses: Vec::new(),
+        };
+
+        assert_eq!(connections_text(Some(&wired), &[], false), "🖧 Wired connection 1");
+        assert_eq!(connections_text(Some(&Connection { state: state::ACTIVATING, ..wired.clone() }), &[], false), "🖧 connecting");
+        assert_eq!(connections_text(Some(&Connection { state: state::DEACTIVATED, ..wired.clone() }), &[], false), "disconnected");
+        assert_eq!(connections_text(None, &[], false), "disconnected");
+
+        let station = Station { ssid: "home".to_owned(), strength: 70, frequency: 5180 };
+
+        let wireless = Connection {
+            path: path("/2"),
+            id: "home 1".to_owned(),
+            kind: Kind::of(kind::WIRELESS),
+            state: state::ACTIVATED,
+            access_point: Some(station.clone()),
+            addresses: vec!["192.168.1.23".parse().unwrap(), "2001:db8::23".parse().unwrap()],
+        };
+
+        assert_eq!(connections_text(Some(&wireless), &[], false), "📶 home ▆ 5GHz");
+
+        let hidden = Station { ssid: String::new(), strength: 0, frequency: 0 };
+        assert_eq!(connections_text(Some(&Connection { access_point: Some(hidden), ..wireless.clone() }), &[], false), "📶 home 1 ▁");
+
+        let vpn = Badge {
+            path: path("/3"),
+            id: "work".to_owned(),
+            kind: Kind::of(kind::WIREGUARD),
+            state: state::ACTIVATED,
+        };
+
+        let ethernet = Badge {
+            path: wired.path,
+            id: wired.id,
+            kind: wired.kind,
+            state: wired.state,
+        };
+
+        assert_eq!(connections_text(Some(&wireless), &[vpn, ethernet], false), "📶 home ▆ 5GHz 🔒 work 🖧 Wired connection 1");
+    }
+
+    #[test]
+    fn address_texts() {
+        let wired = Connection {
+            path: path("/1"),
+            id: "Wired".to_owned(),
+            kind: Kind::Ethernet,
+            state: state::ACTIVATED,
+            access_point: None,
+            addresses: vec!["10.0.0.2".parse().unwrap()],
+        };
+
+        let connections = Status::Connections(Some(wired.clone()), Vec::new());
+        assert_eq!(text(&connections, false).unwrap(), "🖧 Wired");
+        assert_eq!(text(&connections, true).unwrap(), "🖧 Wired 10.0.0.2");
+
+        let connecting = Status::Connections(Some(Connection { state: state::ACTIVATING, ..wired }), Vec::new());
+        assert_eq!(text(&connecting, true).unwrap(), "🖧 connecting");
+
+        let kernel = Status::Addresses(vec!["10.0.0.2".parse().unwrap(), "2001:db8::2".parse().unwrap()]);
+        assert_eq!(text(&kernel, false), None);
+        assert_eq!(text(&kernel, true).unwrap(), "🌐 10.0.0.2 2001:db8::2");
+        assert_eq!(text(&Status::Addresses(Vec::new()), true).unwrap(), "disconnected");
+
+        let data = ["192.168.1.23", "fe80::1", "2001:db8::23"].iter()
+            .map(|address| HashMap::from([
+                ("address".to_owned(), OwnedValue::from(zbus::zvariant::Str::from(*address))),
+                ("prefix".to_owned(), OwnedValue::from(24u32)),
+            ]))
+            .collect::<Vec<_>>();
+
+        assert_eq!(addresses(&data), ["192.168.1.23".parse::<IpAddr>().unwrap(), "2001:db8::23".parse().unwrap()]);
+    }
+
+    #[test]
+    fn options() {
+        let options: Options = toml::from_str("").unwrap();
+        assert_eq!(options.badges, [Kind::Vpn]);
+
+        let options: Options = toml::from_str(r#"badges = ["vpn", "mobile"]"#).unwrap();
+        assert_eq!(options.badges, [Kind::Vpn, Kind::Mobile]);
+
+        assert!(toml::from_str::<Options>(r#"badges = ["carrier-pigeon"]"#).is_err());
+    }
+
+    #[test]
+    fn signals() {
+        assert_eq!(signal(0), '▁');
+        assert_eq!(signal(50), '▅');
+        assert_eq!(signal(100), '█');
+        assert_eq!(signal(255), '█');
+    }
 }
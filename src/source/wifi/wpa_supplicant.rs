@@ -1,73 +1,161 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use futures::Stream;
-use tokio::sync::watch;
-use tokio::io::unix::AsyncFd;
+use futures::future::{self, FutureExt};
+use futures::stream::{self, Stream, StreamExt};
+use serde::Deserialize;
 
+use crate::util;
+use crate::util::backoff;
 use crate::util::wpactrl;
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Options {
+    /// Path to the control socket of the interface, eg.
+    /// `/var/run/wpa_supplicant/wlan0`
+    pub control_path: PathBuf,
+}
+
+/// How long to wait for events before checking wpa_supplicant is still there
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait for a reply. wpa_supplicant answers STATUS straight
+/// away, so one that takes longer is stuck, and the SSID it last gave
+/// shouldn't stay up meanwhile.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The SSID of the network the interface is connected to, or None while
+/// it isn't or wpa_supplicant isn't running. Attaches again with backoff
+/// whenever wpa_supplicant starts later or restarts.
 pub fn ssid(control_path: &Path) -> impl Stream<Item = Option<String>> {
-    let (tx, rx) = watch::channel(None);
+    let control_path = control_path.to_owned();
+
+    let clients = backoff::reconnect(move || {
+        let control_path = control_path.clone();
+
+        async move {
+            attach(&control_path).await
+                .map_err(|e| eprintln!("source::wifi::wpa_supplicant: can't attach to {}: {:?}", control_path.display(), e))
+                .ok()
+        }
+    });
 
-    let attach = wpactrl::Client::builder()
+    // all we know once a client is lost is that we're not connected
+    let ssids = clients.flat_map(|(client, events)| {
+        statuses(client, events).chain(stream::once(future::ready(None)))
+    });
+
+    util::stream::dedup(stream::once(future::ready(None)).chain(ssids))
+}
+
+async fn attach(control_path: &Path) -> wpactrl::Result<(wpactrl::Client, wpactrl::Events)> {
+    let mut client = wpactrl::Client::builder()
         .ctrl_path(control_path)
-        .open()
-        .expect("open error")
-        .attach()
-        .expect("attach error");
+        .timeout(REQUEST_TIMEOUT)
+        .open()?;
 
-    let attach = AsyncFd::new(attach).expect("asyncfd error");
+    let events = client.attach().await?;
+
+    Ok((client, events))
+}
+
+/// The SSID from STATUS up front and after each batch of events, until
+/// wpa_supplicant goes away
+fn statuses(client: wpactrl::Client, events: wpactrl::Events) -> impl Stream<Item = Option<String>> {
+    stream::unfold(Some((client, events, true)), |state| async move {
+        let (mut client, mut events, first) = state?;
+
+        if !first {
+            // wpa_supplicant can't tell us it crashed, but requests fail
+            // once it has
+            let mut event = match tokio::time::timeout(POLL_INTERVAL, events.next()).await {
+                Ok(Some(event)) => Some(event),
+                Ok(None) => return None,
+                Err(_) => None,
+            };
+
+            // one STATUS answers for all the events already queued, eg. a
+            // scan's worth
+            while let Some(queued) = event {
+                if queued.contains("CTRL-EVENT-TERMINATING") {
+                    return None;
+                }
 
-    tokio::spawn(async move {
-        if let Err(e) = run_client(attach, tx).await {
-            eprintln!("wifi: client terminated with error: {:?}", e);
+                event = match events.next().now_or_never() {
+                    Some(Some(event)) => Some(event),
+                    Some(None) => return None,
+                    None => None,
+                };
+            }
         }
-    });
 
-    tokio_stream::wrappers::WatchStream::new(rx)
+        match client.request("STATUS").await {
+            Ok(status) => Some((parse_ssid(&status), Some((client, events, false)))),
+            Err(e) => {
+                eprintln!("source::wifi::wpa_supplicant: client failed: {:?}", e);
+                None
+            }
+        }
+    })
+}
+
+fn parse_ssid(status: &str) -> Option<String> {
+    status.lines()
+        .find_map(|line| line.strip_prefix("ssid="))
+        .map(|ssid| ssid.trim().to_owned())
 }
 
-async fn run_client(
-    mut client: AsyncFd<wpactrl::ClientAttached>,
-    tx: watch::Sender<Option<String>>,
-) -> Result<(), wpactrl::Error> {
-    use tokio::task::block_in_place;
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::net::UnixDatagram;
+
+    use super::*;
 
-    block_in_place(|| client.get_mut().send_request("STATUS").unwrap());
+    #[tokio::test]
+    async fn one_status_per_batch() {
+        let dir = std::env::temp_dir().join(format!("hstatus-wpa-supplicant-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wlan0");
 
-    loop {
-        let mut readable = client.readable_mut().await?;
+        let server = UnixDatagram::bind(&path).unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
 
-        let mut received_unsolicited = false;
+        let _server = util::future::spawn({
+            let requests = requests.clone();
 
-        while let Some(msg) = readable.get_inner_mut().recv()? {
-            if is_message_unsolicited(&msg) {
-                received_unsolicited = true;
-            } else {
-                // we only ever send status command, so all replies must be
-                // statuses. parse for SSID:
+            async move {
+                let mut buffer = vec![0; 4096];
 
-                let ssid = msg.lines()
-                    .find(|line| line.starts_with("ssid="))
-                    .map(|line| line[5..].trim());
+                while let Ok((len, client)) = server.recv_from(&mut buffer).await {
+                    let client = client.as_pathname().unwrap();
 
-                if let Err(_) = tx.send(ssid.map(str::to_owned)) {
-                    return Ok(());
+                    let replies: &[&str] = match &buffer[..len] {
+                        // a burst of events lands before the first STATUS is answered
+                        b"ATTACH" => &["OK\n", "<2>CTRL-EVENT-SCAN-STARTED", "<2>CTRL-EVENT-BSS-ADDED 0", "<2>CTRL-EVENT-BSS-ADDED 1", "<2>CTRL-EVENT-SCAN-RESULTS"],
+                        b"STATUS" => match requests.fetch_add(1, Ordering::SeqCst) {
+                            0 => &["ssid=home\n"],
+                            _ => &["ssid=home\n", "<2>CTRL-EVENT-TERMINATING"],
+                        },
+                        _ => &["OK\n"],
+                    };
+
+                    for reply in replies {
+                        server.send_to(reply.as_bytes(), client).await.unwrap();
+                    }
                 }
             }
-        }
+        });
 
-        readable.clear_ready();
+        let (client, events) = attach(&path).await.unwrap();
+        let ssids = statuses(client, events).collect::<Vec<_>>().await;
 
-        if received_unsolicited {
-            block_in_place(|| client.get_mut().send_request("STATUS").unwrap());
-        }
-    }
-}
-
-fn is_message_unsolicited(msg: &str) -> bool {
-    // logic taken from hostapd/src/command/wpa_ctrl.c
-    // wpa_ctrl_request function
+        assert_eq!(ssids, [Some("home".to_owned()), Some("home".to_owned())]);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
 
-    msg.starts_with("<") || msg.starts_with("IFNAME=")
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,32 +1,182 @@
+use std::collections::HashMap;
 use std::pin::Pin;
 use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
 
+use crate::click::{self, Action, Button};
 use crate::util;
 
 pub struct LineBuilder {
-    sources: Vec<Pin<Box<dyn Stream<Item = Option<String>>>>>,
+    sources: Vec<Pin<Box<dyn Stream<Item = Option<Block>>>>>,
+    clicks: click::Handler,
+}
+
+/// Messages sent to a source by click actions
+pub type Messages = UnboundedReceiverStream<String>;
+
+/// What a source shows in its segment
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Value {
+    pub text: String,
+    /// Overrides the segment's color
+    pub color: Option<String>,
+    pub urgent: bool,
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value { text, ..Value::default() }
+    }
+}
+
+/// Describes how a source is presented on the bar. Each segment becomes one
+/// block in the i3bar protocol.
+#[derive(Clone)]
+pub struct Segment {
+    pub name: String,
+    pub instance: Option<String>,
+    pub icon: String,
+    pub color: Option<String>,
+    pub separator: Option<bool>,
+    pub min_width: Option<MinWidth>,
+    actions: HashMap<Button, Action>,
+    messages: Option<mpsc::UnboundedSender<String>>,
+}
+
+/// A single block of the i3bar protocol, see swaybar-protocol(7).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Block {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub full_text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub short_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub separator: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_width: Option<MinWidth>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub urgent: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MinWidth {
+    Pixels(u32),
+    Text(String),
+}
+
+impl Segment {
+    pub fn new(name: impl Into<String>, icon: impl Into<String>) -> Self {
+        Segment {
+            name: name.into(),
+            instance: None,
+            icon: icon.into(),
+            color: None,
+            separator: None,
+            min_width: None,
+            actions: HashMap::new(),
+            messages: None,
+        }
+    }
+
+    pub fn on_click(mut self, button: Button, action: Action) -> Self {
+        self.actions.insert(button, action);
+        self
+    }
+
+    pub(crate) fn actions(&self) -> &HashMap<Button, Action> {
+        &self.actions
+    }
+
+    /// Sets where messages sent by this segment's `Action::Message` click
+    /// actions go, usually the source's `Messages`.
+    pub fn set_messages(&mut self, tx: mpsc::UnboundedSender<String>) {
+        self.messages = Some(tx);
+    }
+
+    pub(crate) fn message_sender(&self) -> Option<mpsc::UnboundedSender<String>> {
+        self.messages.clone()
+    }
+
+    fn block(&self, value: Value) -> Block {
+        let full_text = match self.icon.is_empty() {
+            true => value.text.clone(),
+            false => format!("{} {}", self.icon, value.text),
+        };
+
+        Block {
+            name: self.name.clone(),
+            instance: self.instance.clone(),
+            full_text,
+            short_text: Some(value.text),
+            color: value.color.or_else(|| self.color.clone()),
+            separator: self.separator,
+            min_width: self.min_width.clone(),
+            urgent: value.urgent,
+        }
+    }
+}
+
+impl Block {
+    /// A block not belonging to any segment, used for flash messages
+    pub fn text(name: &str, text: String) -> Self {
+        Block {
+            name: name.to_owned(),
+            instance: None,
+            full_text: text,
+            short_text: None,
+            color: None,
+            separator: None,
+            min_width: None,
+            urgent: false,
+        }
+    }
 }
 
 impl LineBuilder {
     pub fn new() -> Self {
-        LineBuilder { sources: Vec::new() }
+        LineBuilder { sources: Vec::new(), clicks: click::Handler::default() }
     }
 
-    pub fn segment(mut self, emoji: &'static str, source: impl Stream<Item = Option<String>> + 'static) -> Self {
-        let source = source.map(move |suffix| {
-            suffix.map(|suffix| format!("{} {}", emoji, suffix))
+    pub fn segment(mut self, segment: Segment, source: impl Stream<Item = Option<Value>> + 'static) -> Self {
+        self.clicks.register(&segment);
+
+        let source = source.map(move |value| {
+            value.map(|value| segment.block(value))
         });
 
-        self.sources.push(Box::pin(source) as Pin<Box<dyn Stream<Item = Option<String>>>>);
+        self.sources.push(Box::pin(source) as Pin<Box<dyn Stream<Item = Option<Block>>>>);
 
         self
     }
 
-    pub fn build(self) -> impl Stream<Item = String> {
-        util::stream::combine_all(self.sources)
+    /// Returns the stream of status lines along with the handler routing
+    /// click events to the segments' actions.
+    pub fn build(self) -> (impl Stream<Item = Vec<Block>>, click::Handler) {
+        let line = util::stream::combine_all(self.sources)
             .map(|segments| segments.into_iter()
                 .filter_map(|segment| segment.flatten())
-                .collect::<Vec<_>>())
-            .map(|segments| segments.join("   "))
+                .collect::<Vec<_>>());
+
+        (line, self.clicks)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn icons() {
+        let value = Value::from("42%".to_owned());
+
+        assert_eq!(Segment::new("battery", "🔋").block(value.clone()).full_text, "🔋 42%");
+        assert_eq!(Segment::new("battery", "").block(value).full_text, "42%");
     }
 }
@@ -0,0 +1,85 @@
+use std::future::Future;
+use std::time::Duration;
+
+use futures::stream::{self, Stream};
+
+/// Delays between retries, doubling after each failed attempt up to a limit
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    delay: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Backoff { delay: min, min, max }
+    }
+
+    /// The delay before the next attempt
+    pub fn next(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.max);
+        delay
+    }
+
+    pub async fn wait(&mut self) {
+        tokio::time::sleep(self.next()).await;
+    }
+
+    /// Starts over from the shortest delay, eg. once an attempt succeeds
+    pub fn reset(&mut self) {
+        self.delay = self.min;
+    }
+}
+
+/// Connections made with `connect`, each once the last is done with.
+/// Failed attempts are retried with backoff, and there's a short wait
+/// before connecting again so that a connection failing straight away
+/// isn't retried in a busy loop.
+pub fn reconnect<T, F, Fut>(connect: F) -> impl Stream<Item = T>
+    where F: FnMut() -> Fut,
+          Fut: Future<Output = Option<T>>,
+{
+    stream::unfold((connect, None), |(mut connect, backoff): (F, Option<Backoff>)| async move {
+        let mut backoff = match backoff {
+            Some(mut backoff) => {
+                backoff.wait().await;
+                backoff
+            }
+            None => Backoff::default(),
+        };
+
+        loop {
+            match connect().await {
+                Some(connection) => {
+                    backoff.reset();
+                    return Some((connection, (connect, Some(backoff))));
+                }
+                None => backoff.wait().await,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+
+        let delays = (0..4).map(|_| backoff.next().as_secs()).collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 5]);
+
+        backoff.reset();
+        assert_eq!(backoff.next(), Duration::from_secs(1));
+    }
+}
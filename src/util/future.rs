@@ -1,18 +1,15 @@
 use futures::Future;
-use std::time::Duration;
 
-pub struct Defer(tokio::task::JoinHandle<()>);
+/// A spawned task which is aborted when dropped
+pub struct Task(tokio::task::JoinHandle<()>);
 
-pub fn defer<Fut>(duration: Duration, task: Fut) -> Defer
+pub fn spawn<Fut>(task: Fut) -> Task
     where Fut: Future<Output = ()> + Send + 'static
 {
-    Defer(tokio::spawn(async move {
-        tokio::time::sleep(duration).await;
-        task.await
-    }))
+    Task(tokio::spawn(task))
 }
 
-impl Drop for Defer {
+impl Drop for Task {
     fn drop(&mut self) {
         self.0.abort()
     }
@@ -1,4 +1,11 @@
-pub mod file_contents;
+pub mod backoff;
 pub mod future;
+pub mod netlink;
+pub mod process;
+pub mod realtime;
+pub mod rtnetlink;
+pub mod socket;
 pub mod stream;
+pub mod uevent;
+pub mod watch;
 pub mod wpactrl;
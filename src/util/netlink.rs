@@ -0,0 +1,126 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::task::{Context, Poll};
+
+use futures::{future, ready};
+use tokio::io::unix::AsyncFd;
+
+/// A netlink socket talking to the kernel, see netlink(7)
+pub struct Netlink(AsyncFd<OwnedFd>);
+
+impl Netlink {
+    /// Opens a socket of `kind`, eg. `SOCK_RAW`, for the netlink `protocol`,
+    /// subscribed to the multicast `groups`
+    pub fn open(kind: libc::c_int, protocol: libc::c_int, groups: u32) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                kind | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                protocol,
+            )
+        };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = groups;
+
+        let r = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Netlink(AsyncFd::new(fd)?))
+    }
+
+    /// Sends a message to the kernel
+    pub async fn send(&self, msg: &[u8]) -> io::Result<()> {
+        loop {
+            let mut guard = self.0.writable().await?;
+
+            let result = guard.try_io(|fd| {
+                let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+                addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+
+                let len = unsafe {
+                    libc::sendto(
+                        fd.as_raw_fd(),
+                        msg.as_ptr() as *const libc::c_void,
+                        msg.len(),
+                        0,
+                        &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                    )
+                };
+
+                if len < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                Ok(())
+            });
+
+            match result {
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Receives a datagram into `buf`, returning its length, or 0 if it
+    /// wasn't sent by the kernel
+    pub fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.0.poll_read_ready(cx))?;
+
+            let result = guard.try_io(|fd| {
+                let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+                let mut addr_len = mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+
+                let len = unsafe {
+                    libc::recvfrom(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        0,
+                        &mut addr as *mut libc::sockaddr_nl as *mut libc::sockaddr,
+                        &mut addr_len,
+                    )
+                };
+
+                if len < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                // only trust messages sent by the kernel itself
+                if addr.nl_pid != 0 {
+                    return Ok(0);
+                }
+
+                Ok(len as usize)
+            });
+
+            match result {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        future::poll_fn(|cx| self.poll_recv(cx, buf)).await
+    }
+}
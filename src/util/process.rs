@@ -0,0 +1,18 @@
+use std::process::Stdio;
+
+use tokio::process::Command;
+
+/// Runs a shell command in the background. Its stdout is discarded as ours
+/// is the status line.
+pub fn spawn(command: &str) {
+    let result = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn();
+
+    if let Err(e) = result {
+        eprintln!("failed to spawn {:?}: {:?}", command, e);
+    }
+}
@@ -0,0 +1,101 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::ptr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::io::unix::AsyncFd;
+
+/// A timer on the wall clock, see timerfd_create(2). Unlike tokio's timers
+/// it fires at the right time after a suspend, and it notices when the
+/// clock is set.
+pub struct Timer(AsyncFd<OwnedFd>);
+
+pub fn timer() -> io::Result<Timer> {
+    let fd = unsafe {
+        libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_CLOEXEC | libc::TFD_NONBLOCK)
+    };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    Ok(Timer(AsyncFd::new(fd)?))
+}
+
+impl Timer {
+    /// Waits until the wall clock reaches `deadline`, or until the clock is
+    /// set, eg. by NTP or after resuming from suspend
+    pub async fn sleep_until(&self, deadline: SystemTime) -> io::Result<()> {
+        let deadline = deadline.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let mut spec: libc::itimerspec = unsafe { mem::zeroed() };
+        spec.it_value.tv_sec = deadline.as_secs() as libc::time_t;
+        spec.it_value.tv_nsec = deadline.subsec_nanos() as libc::c_long;
+
+        // arming the timer also resets any expiry we didn't read
+        let r = unsafe {
+            libc::timerfd_settime(
+                self.0.as_raw_fd(),
+                libc::TFD_TIMER_ABSTIME | libc::TFD_TIMER_CANCEL_ON_SET,
+                &spec,
+                ptr::null_mut(),
+            )
+        };
+
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        loop {
+            let mut guard = self.0.readable().await?;
+
+            let result = guard.try_io(|fd| {
+                let mut expirations = 0u64;
+
+                let len = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        &mut expirations as *mut u64 as *mut libc::c_void,
+                        mem::size_of::<u64>(),
+                    )
+                };
+
+                if len < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                Ok(())
+            });
+
+            match result {
+                Ok(Ok(())) => return Ok(()),
+                // the clock was set
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ECANCELED) => return Ok(()),
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn sleep_until() {
+        let timer = timer().unwrap();
+        let start = Instant::now();
+
+        timer.sleep_until(SystemTime::now() + Duration::from_millis(20)).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(15));
+
+        // deadlines in the past expire straight away
+        timer.sleep_until(SystemTime::now() - Duration::from_secs(1)).await.unwrap();
+    }
+}
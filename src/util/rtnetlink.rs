@@ -0,0 +1,221 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use futures::stream::{self, Stream};
+
+use crate::util::netlink::Netlink;
+
+const BUF_SIZE: usize = 32768;
+
+const NLMSG_HDRLEN: usize = 16;
+const IFADDRMSG_LEN: usize = 8;
+const RTA_HDRLEN: usize = 4;
+
+/// A socket subscribed to addresses being added and removed, see
+/// rtnetlink(7)
+pub fn listen() -> io::Result<Netlink> {
+    socket((libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32)
+}
+
+fn socket(groups: u32) -> io::Result<Netlink> {
+    Netlink::open(libc::SOCK_RAW, libc::NETLINK_ROUTE, groups)
+}
+
+/// The global addresses of all interfaces, leaving out loopback and
+/// link-local addresses
+pub async fn addresses() -> io::Result<Vec<IpAddr>> {
+    let socket = socket(0)?;
+    socket.send(&dump_request()).await?;
+
+    let mut buf = vec![0; BUF_SIZE];
+    let mut addresses = Vec::new();
+
+    loop {
+        let len = socket.recv(&mut buf).await?;
+
+        if parse(&buf[..len], &mut addresses)? {
+            return Ok(addresses);
+        }
+    }
+}
+
+/// Yields each time an address is added or removed, until the socket fails
+pub fn changes(socket: Netlink) -> impl Stream<Item = ()> {
+    let buf = vec![0; BUF_SIZE];
+
+    stream::unfold((socket, buf), |(socket, mut buf)| async move {
+        loop {
+            match socket.recv(&mut buf).await {
+                Ok(0) => {}
+                Ok(_) => return Some(((), (socket, buf))),
+                // the receive buffer overflowed and some changes were lost,
+                // which is still a change
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => return Some(((), (socket, buf))),
+                Err(e) => {
+                    eprintln!("rtnetlink: receive failed: {:?}", e);
+                    return None;
+                }
+            }
+        }
+    })
+}
+
+/// RTM_GETADDR for all address families
+fn dump_request() -> Vec<u8> {
+    let len = NLMSG_HDRLEN + IFADDRMSG_LEN;
+    let mut msg = Vec::with_capacity(len);
+
+    msg.extend_from_slice(&(len as u32).to_ne_bytes());
+    msg.extend_from_slice(&libc::RTM_GETADDR.to_ne_bytes());
+    msg.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    // sequence number and port ID
+    msg.extend_from_slice(&1u32.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    // ifaddrmsg with AF_UNSPEC
+    msg.extend_from_slice(&[0; IFADDRMSG_LEN]);
+
+    msg
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(buf.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(buf.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// Adds the global addresses in a datagram of a dump to `addresses`,
+/// returning whether the dump is done
+fn parse(mut buf: &[u8], addresses: &mut Vec<IpAddr>) -> io::Result<bool> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated netlink message");
+
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32_at(buf, 0).ok_or_else(invalid)? as usize;
+        let kind = u16_at(buf, 4).ok_or_else(invalid)?;
+        let msg = buf.get(NLMSG_HDRLEN..len).ok_or_else(invalid)?;
+
+        match kind as libc::c_int {
+            libc::NLMSG_DONE => return Ok(true),
+            libc::NLMSG_ERROR => {
+                let errno = u32_at(msg, 0).ok_or_else(invalid)? as i32;
+
+                if errno != 0 {
+                    return Err(io::Error::from_raw_os_error(-errno));
+                }
+            }
+            _ if kind == libc::RTM_NEWADDR => {
+                addresses.extend(address(msg));
+            }
+            _ => {}
+        }
+
+        buf = buf.get(align(len)..).unwrap_or_default();
+    }
+
+    Ok(false)
+}
+
+/// The address of an RTM_NEWADDR message, if it's global
+fn address(msg: &[u8]) -> Option<IpAddr> {
+    let family = *msg.first()? as libc::c_int;
+    let scope = *msg.get(3)?;
+
+    if scope != libc::RT_SCOPE_UNIVERSE {
+        return None;
+    }
+
+    let mut attrs = msg.get(IFADDRMSG_LEN..)?;
+    let mut address = None;
+
+    while attrs.len() >= RTA_HDRLEN {
+        let len = u16_at(attrs, 0)? as usize;
+        let kind = u16_at(attrs, 2)?;
+        let data = attrs.get(RTA_HDRLEN..len)?;
+
+        let ip = match family {
+            libc::AF_INET => <[u8; 4]>::try_from(data).ok().map(Ipv4Addr::from).map(IpAddr::from),
+            libc::AF_INET6 => <[u8; 16]>::try_from(data).ok().map(Ipv6Addr::from).map(IpAddr::from),
+            _ => None,
+        };
+
+        // IFA_ADDRESS is the peer's address on point-to-point links, so
+        // prefer IFA_LOCAL
+        match kind {
+            libc::IFA_LOCAL => return ip,
+            libc::IFA_ADDRESS => address = ip,
+            _ => {}
+        }
+
+        attrs = attrs.get(align(len)..).unwrap_or_default();
+    }
+
+    address
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(kind: u16, body: &[u8]) -> Vec<u8> {
+        let len = NLMSG_HDRLEN + body.len();
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&(len as u32).to_ne_bytes());
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.extend_from_slice(&[0; 10]);
+        msg.extend_from_slice(body);
+        msg.resize(align(len), 0);
+        msg
+    }
+
+    fn newaddr(family: libc::c_int, scope: u8, attrs: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut body = vec![family as u8, 24, 0, scope, 2, 0, 0, 0];
+
+        for (kind, data) in attrs {
+            let len = RTA_HDRLEN + data.len();
+            body.extend_from_slice(&(len as u16).to_ne_bytes());
+            body.extend_from_slice(&kind.to_ne_bytes());
+            body.extend_from_slice(data);
+            body.resize(align(body.len()), 0);
+        }
+
+        message(libc::RTM_NEWADDR, &body)
+    }
+
+    #[test]
+    fn parse_dump() {
+        let v6: Ipv6Addr = "2001:db8::23".parse().unwrap();
+        let link_local: Ipv6Addr = "fe80::1".parse().unwrap();
+
+        let mut buf = Vec::new();
+        buf.extend(newaddr(libc::AF_INET, 0, &[(libc::IFA_ADDRESS, &[10, 0, 0, 1]), (libc::IFA_LOCAL, &[192, 168, 1, 23])]));
+        buf.extend(newaddr(libc::AF_INET6, 253, &[(libc::IFA_ADDRESS, &link_local.octets())]));
+        buf.extend(newaddr(libc::AF_INET6, 0, &[(libc::IFA_ADDRESS, &v6.octets())]));
+
+        let mut addresses = Vec::new();
+        assert!(!parse(&buf, &mut addresses).unwrap());
+        assert!(parse(&message(libc::NLMSG_DONE as u16, &[0; 4]), &mut addresses).unwrap());
+
+        assert_eq!(addresses, [IpAddr::from([192, 168, 1, 23]), IpAddr::from(v6)]);
+
+        let error = message(libc::NLMSG_ERROR as u16, &(-libc::EPERM).to_ne_bytes());
+        assert_eq!(parse(&error, &mut addresses).unwrap_err().raw_os_error(), Some(libc::EPERM));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs network interfaces"]
+    async fn dump() {
+        let addresses = addresses().await.unwrap();
+
+        // there's always a loopback interface to leave out
+        assert!(addresses.iter().all(|address| !address.is_loopback()));
+        assert!(addresses.iter().all(|address| match address {
+            IpAddr::V4(address) => !address.is_link_local(),
+            IpAddr::V6(address) => !address.is_unicast_link_local(),
+        }));
+    }
+}
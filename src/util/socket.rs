@@ -0,0 +1,233 @@
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{self as std_net, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+
+use futures::{Stream, StreamExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio_stream::wrappers::UnixListenerStream;
+
+/// Users allowed to connect to the bar's sockets. Our own user always is.
+#[derive(Clone, Debug, Default)]
+pub struct Peers {
+    uids: Arc<[u32]>,
+}
+
+impl Peers {
+    pub fn new(uids: Vec<u32>) -> Self {
+        Peers { uids: uids.into() }
+    }
+
+    /// Checks who's on the other end of `socket` with SO_PEERCRED
+    pub fn allows(&self, socket: &UnixStream) -> bool {
+        match socket.peer_cred() {
+            Ok(cred) => cred.uid() == euid() || self.uids.contains(&cred.uid()),
+            Err(e) => {
+                eprintln!("util::socket: can't tell who connected: {:?}", e);
+                false
+            }
+        }
+    }
+}
+
+fn euid() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+/// The first file descriptor passed by systemd, see sd_listen_fds(3)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The listening socket systemd passed us with `FileDescriptorName=name`.
+/// A single socket passed without a name is taken to be the flash socket.
+pub fn activated(name: &str) -> Option<io::Result<UnixListener>> {
+    let pid = std::env::var("LISTEN_PID").ok()?;
+    let fds = std::env::var("LISTEN_FDS").ok()?;
+    let names = std::env::var("LISTEN_FDNAMES").ok();
+
+    let fd = activated_fd(name, &pid, &fds, names.as_deref(), std::process::id())?;
+
+    Some(listener(fd))
+}
+
+fn activated_fd(name: &str, pid: &str, fds: &str, names: Option<&str>, our_pid: u32) -> Option<RawFd> {
+    // the variables are inherited by our children, but aren't meant for them
+    if pid.parse::<u32>().ok()? != our_pid {
+        return None;
+    }
+
+    let count = fds.parse::<RawFd>().ok()?;
+
+    let index = match names {
+        Some(names) => names.split(':').position(|fd_name| fd_name == name)? as RawFd,
+        None if count == 1 && name == "flash" => 0,
+        None => return None,
+    };
+
+    (index < count).then_some(LISTEN_FDS_START + index)
+}
+
+fn listener(fd: RawFd) -> io::Result<UnixListener> {
+    // systemd doesn't set close-on-exec, but our children shouldn't hold
+    // the socket open
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let listener = unsafe { std_net::UnixListener::from_raw_fd(fd) };
+    listener.set_nonblocking(true)?;
+
+    UnixListener::from_std(listener)
+}
+
+/// Linux abstract socket names are written with a leading `@`
+fn abstract_name(path: &Path) -> Option<&[u8]> {
+    path.as_os_str().as_bytes().strip_prefix(b"@")
+}
+
+/// Listens on a socket at `path`, or with an abstract name like `@hstatus`,
+/// that only our user can connect to. Replaces a socket we left behind, but
+/// not one still being listened on, nor anything else.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    if let Some(name) = abstract_name(path) {
+        // abstract sockets have no permissions and vanish when closed, so
+        // Peers::allows is all that keeps other users out
+        let listener = std_net::UnixListener::bind_addr(&SocketAddr::from_abstract_name(name)?)?;
+        listener.set_nonblocking(true)?;
+        return UnixListener::from_std(listener);
+    }
+
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() && meta.uid() == euid() => {
+            match std_net::UnixStream::connect(path) {
+                Ok(_) => {
+                    return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                        "another process is listening on it"));
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path)?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(_) => {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                "path exists and isn't a socket of ours"));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let listener = bind_private(path)?;
+    listener.set_nonblocking(true)?;
+
+    UnixListener::from_std(listener)
+}
+
+/// Binds a socket in a directory only we can get into, and moves it to
+/// `path` once only we can connect to it
+fn bind_private(path: &Path) -> io::Result<std_net::UnixListener> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let private = dir.join(format!(".hstatus-{}", std::process::id()));
+
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+    let bound = (|| {
+        let temp = private.join("s");
+        let listener = std_net::UnixListener::bind(&temp)?;
+        fs::set_permissions(&temp, Permissions::from_mode(0o600))?;
+        fs::rename(&temp, path)?;
+        Ok(listener)
+    })();
+
+    fs::remove_dir_all(&private)?;
+
+    bound
+}
+
+/// Connects to the socket at `path`, or with an abstract name like
+/// `@hstatus`
+pub async fn connect(path: &Path) -> io::Result<UnixStream> {
+    match abstract_name(path) {
+        Some(name) => {
+            // connecting to a unix socket doesn't block
+            let socket = std_net::UnixStream::connect_addr(&SocketAddr::from_abstract_name(name)?)?;
+            socket.set_nonblocking(true)?;
+            UnixStream::from_std(socket)
+        }
+        None => UnixStream::connect(path).await,
+    }
+}
+
+/// Connections accepted on `listener` from allowed peers
+pub fn incoming(listener: UnixListener, peers: Peers) -> impl Stream<Item = io::Result<UnixStream>> {
+    UnixListenerStream::new(listener)
+        .filter(move |socket| {
+            let allowed = match socket {
+                Ok(socket) => peers.allows(socket),
+                Err(_) => true,
+            };
+
+            if !allowed {
+                eprintln!("util::socket: refused connection from another user");
+            }
+
+            futures::future::ready(allowed)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn bind() {
+        let dir = std::env::temp_dir().join(format!("hstatus-socket-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.sock");
+
+        let listener = super::bind(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+
+        let client = UnixStream::connect(&path).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        assert!(Peers::default().allows(&server));
+
+        // a socket still being listened on is left alone
+        assert_eq!(super::bind(&path).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        drop((client, server, listener));
+
+        // our own stale socket is replaced
+        super::bind(&path).unwrap();
+
+        let file = dir.join("test.txt");
+        fs::write(&file, "keep me").unwrap();
+        assert!(super::bind(&file).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep me");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn abstract_names() {
+        let path = format!("@hstatus-socket-test-{}", std::process::id());
+
+        let listener = super::bind(Path::new(&path)).unwrap();
+        let _client = connect(Path::new(&path)).await.unwrap();
+        listener.accept().await.unwrap();
+
+        assert!(super::bind(Path::new(&path)).is_err());
+    }
+
+    #[test]
+    fn activation() {
+        assert_eq!(activated_fd("flash", "42", "1", None, 42), Some(3));
+        assert_eq!(activated_fd("control", "42", "1", None, 42), None);
+        assert_eq!(activated_fd("flash", "41", "1", None, 42), None);
+        assert_eq!(activated_fd("control", "42", "2", Some("flash:control"), 42), Some(4));
+        assert_eq!(activated_fd("control", "42", "1", Some("flash:control"), 42), None);
+        assert_eq!(activated_fd("flash", "42", "1", Some("other"), 42), None);
+    }
+}
@@ -77,15 +77,20 @@ pub fn from_future<T>(fut: impl Future<Output = impl Stream<Item = T>> + 'static
     })
 }
 
+/// Items of the latest stream yielded by `stream`, ending once both it and
+/// the latest stream have
 pub fn follow_latest<T>(stream: impl Stream<Item = impl Stream<Item = T>>) -> impl Stream<Item = T> {
-    let mut outer = Box::pin(stream);
+    let mut outer = Some(Box::pin(stream));
     let mut inner_slot = None;
 
     stream::poll_fn(move |cx| {
-        match outer.as_mut().poll_next(cx) {
-            Poll::Ready(Some(inner)) => { inner_slot = Some(Box::pin(inner)); }
-            Poll::Ready(None) => { return Poll::Ready(None); }
-            Poll::Pending => {}
+        // poll until pending so we're woken for the next inner stream
+        while let Some(stream) = outer.as_mut() {
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(inner)) => { inner_slot = Some(Box::pin(inner)); }
+                Poll::Ready(None) => { outer = None; }
+                Poll::Pending => { break; }
+            }
         }
 
         if let Some(inner) = inner_slot.as_mut() {
@@ -96,7 +101,10 @@ pub fn follow_latest<T>(stream: impl Stream<Item = impl Stream<Item = T>>) -> im
             }
         }
 
-        Poll::Pending
+        match (&outer, &inner_slot) {
+            (None, None) => Poll::Ready(None),
+            _ => Poll::Pending,
+        }
     })
 }
 
@@ -106,3 +114,24 @@ pub fn flatten_result_stream<T, E>(stream: Result<impl Stream<Item = Result<T, E
         Ok(s) => Either::Right(s),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn follow_latest_outlives_outer() {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let inner = tokio_stream::wrappers::UnboundedReceiverStream::new(rx);
+
+        let mut items = Box::pin(follow_latest(stream::iter([stream::iter(vec![1]).boxed(), inner.boxed()])));
+
+        tx.send(2).unwrap();
+        assert_eq!(items.next().await, Some(2));
+        tx.send(3).unwrap();
+        assert_eq!(items.next().await, Some(3));
+
+        drop(tx);
+        assert_eq!(items.next().await, None);
+    }
+}
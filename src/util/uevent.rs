@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::io;
+use std::task::{Context, Poll};
+
+use futures::ready;
+use futures::stream::{self, Stream};
+use tokio::io::ReadBuf;
+use tokio::net::UnixDatagram;
+
+use crate::util::netlink::Netlink;
+
+const BUF_SIZE: usize = 8192;
+
+/// Kernel uevents are multicast to this netlink group
+const KERNEL_GROUP: u32 = 1;
+
+/// A kernel uevent, eg. a power supply changing state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uevent {
+    pub action: String,
+    pub devpath: String,
+    pub properties: HashMap<String, String>,
+}
+
+impl Uevent {
+    /// Parses a uevent in the kernel's format: `ACTION@DEVPATH` followed by
+    /// `KEY=VALUE` properties, separated by nul bytes
+    pub fn parse(msg: &[u8]) -> Option<Uevent> {
+        let msg = std::str::from_utf8(msg).ok()?;
+        let mut fields = msg.split('\0');
+
+        let (action, devpath) = fields.next()?.split_once('@')?;
+
+        let properties = fields
+            .filter_map(|field| field.split_once('='))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+
+        Some(Uevent { action: action.to_owned(), devpath: devpath.to_owned(), properties })
+    }
+
+    pub fn subsystem(&self) -> Option<&str> {
+        self.properties.get("SUBSYSTEM").map(String::as_str)
+    }
+}
+
+/// A datagram socket uevents can be received from. This is a netlink
+/// socket in practice, and a unix socket in tests.
+pub trait Socket {
+    fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>>;
+}
+
+impl Socket for UnixDatagram {
+    fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(UnixDatagram::poll_recv(self, cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+/// A netlink socket subscribed to kernel uevents
+pub fn listen() -> io::Result<Netlink> {
+    Netlink::open(libc::SOCK_DGRAM, libc::NETLINK_KOBJECT_UEVENT, KERNEL_GROUP)
+}
+
+impl Socket for Netlink {
+    fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Netlink::poll_recv(self, cx, buf)
+    }
+}
+
+/// Receives uevents from `socket` until it fails
+pub fn events(socket: impl Socket) -> impl Stream<Item = Uevent> {
+    let mut buf = vec![0; BUF_SIZE];
+
+    stream::poll_fn(move |cx| loop {
+        match ready!(socket.poll_recv(cx, &mut buf)) {
+            Ok(len) => {
+                if let Some(event) = Uevent::parse(&buf[..len]) {
+                    return Poll::Ready(Some(event));
+                }
+            }
+            // the receive buffer overflowed and some events were lost,
+            // there's nothing to do but carry on with the next one
+            Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {}
+            Err(e) => {
+                eprintln!("uevent: receive failed: {:?}", e);
+                return Poll::Ready(None);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn fake_socket() {
+        let (kernel, socket) = UnixDatagram::pair().unwrap();
+
+        kernel.send(b"change@/devices/LNXSYSTM:00/PNP0C0A:00/power_supply/BAT0\0\
+            ACTION=change\0\
+            SUBSYSTEM=power_supply\0\
+            POWER_SUPPLY_STATUS=Charging\0").await.unwrap();
+        kernel.send(b"libudev\0not a kernel event").await.unwrap();
+        kernel.send(b"remove@/devices/platform/thinkpad_acpi\0SUBSYSTEM=platform\0").await.unwrap();
+
+        let events = events(socket).take(2).collect::<Vec<_>>().await;
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].action, "change");
+        assert_eq!(events[0].devpath, "/devices/LNXSYSTM:00/PNP0C0A:00/power_supply/BAT0");
+        assert_eq!(events[0].subsystem(), Some("power_supply"));
+        assert_eq!(events[0].properties["POWER_SUPPLY_STATUS"], "Charging");
+        assert_eq!(events[1].action, "remove");
+        assert_eq!(events[1].subsystem(), Some("platform"));
+    }
+}
@@ -0,0 +1,37 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+
+use futures::future;
+use futures::stream::{Stream, StreamExt};
+use inotify::{Inotify, WatchMask};
+
+/// Yields on each of the `events` for the file at `path`, eg.
+/// `CLOSE_WRITE | MOVED_TO` for writes and replacements
+pub fn file(path: &Path, events: WatchMask) -> io::Result<impl Stream<Item = ()>> {
+    // editors often replace the file rather than writing to it, so watch
+    // the directory and pick out the events for the file's name
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+
+    let name = path.file_name()
+        .map(OsString::from)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+
+    let inotify = Inotify::init()?;
+    inotify.watches().add(dir, events)?;
+
+    let events = inotify.into_event_stream([0; 1024])?
+        .filter_map(move |event| future::ready(match event {
+            Ok(event) if event.name.as_ref() == Some(&name) => Some(()),
+            Ok(_) => None,
+            Err(e) => {
+                eprintln!("util::watch: inotify error: {:?}", e);
+                None
+            }
+        }));
+
+    Ok(events)
+}
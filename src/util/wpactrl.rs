@@ -25,8 +25,18 @@
 
 #![deny(missing_docs)]
 
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{io, str};
 
+use tokio::net::UnixDatagram;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use crate::util::future::{self, Task};
+
 /// The errors that may occur using `wpactrl`
 #[derive(Debug)]
 pub enum Error {
@@ -39,8 +49,8 @@ pub enum Error {
     /// Represents a failed `ATTACH` request to wpasupplicant.
     Attach,
 
-    /// Error waiting for a response
-    Wait
+    /// No reply to a request arrived in time
+    Timeout,
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -48,7 +58,7 @@ pub type Result<T> = ::std::result::Result<T, Error>;
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
-            Self::Attach|Self::Wait => None,
+            Self::Attach|Self::Timeout => None,
             Self::Io(ref source) => Some(source),
             Self::Utf8ToStr(ref source) => Some(source),
         }
@@ -61,8 +71,8 @@ impl std::fmt::Display for Error {
             Self::Attach => {
                 write!(f, "Failed to attach to wpasupplicant")
             }
-            Self::Wait => {
-                write!(f, "Unable to wait for response from wpasupplicant")
+            Self::Timeout => {
+                write!(f, "Timed out waiting for a response from wpasupplicant")
             }
             Self::Io(ref err) => {
                 write!(f, "Failed to execute the specified command: {}", err)
@@ -86,21 +96,21 @@ impl From<str::Utf8Error> for Error {
     }
 }
 
-use std::collections::VecDeque;
-use std::os::unix::io::{AsRawFd, RawFd};
-use std::os::unix::net::UnixDatagram;
-use std::path::{Path, PathBuf};
-use std::time::Duration;
-
 const BUF_SIZE: usize = 10_240;
 const PATH_DEFAULT_CLIENT: &str = "/tmp";
 const PATH_DEFAULT_SERVER: &str = "/var/run/wpa_supplicant/wlan0";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Numbers the paths clients bind to, so that each client in the process
+/// gets its own
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 /// Builder object used to construct a [`Client`] session
 #[derive(Default)]
 pub struct ClientBuilder {
     cli_path: Option<PathBuf>,
     ctrl_path: Option<PathBuf>,
+    timeout: Option<Duration>,
 }
 
 impl ClientBuilder {
@@ -126,7 +136,15 @@ impl ClientBuilder {
         self
     }
 
-    /// Open a control interface to `wpa_supplicant` / `hostapd`.
+    /// How long to wait for the reply to each request, 10 seconds unless set
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Open a control interface to `wpa_supplicant` / `hostapd`. Must be
+    /// called from within a tokio runtime.
     ///
     /// # Examples
     ///
@@ -138,9 +156,8 @@ impl ClientBuilder {
     ///
     /// * [[`Error::Io`]] - Low-level I/O error
     pub fn open(self) -> Result<Client> {
-        let mut counter = 0;
         loop {
-            counter += 1;
+            let counter = COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
             let bind_filename = format!("wpa_ctrl_{}-{}", std::process::id(), counter);
             let bind_filepath = self
                 .cli_path
@@ -149,117 +166,49 @@ impl ClientBuilder {
                 .join(bind_filename);
             match UnixDatagram::bind(&bind_filepath) {
                 Ok(socket) => {
+                    // unlinks the bound path if connecting fails
+                    let bound = Bound(bind_filepath);
                     socket.connect(self.ctrl_path.unwrap_or_else(|| PATH_DEFAULT_SERVER.into()))?;
-                    socket.set_nonblocking(true)?;
-                    return Ok(Client(ClientInternal {
-                        buffer: [0; BUF_SIZE],
-                        handle: socket,
-                        filepath: bind_filepath,
-                    }));
-                }
-                Err(ref e) if counter < 2 && e.kind() == std::io::ErrorKind::AddrInUse => {
-                    std::fs::remove_file(bind_filepath)?;
-                    continue;
+                    return Ok(Client::new(socket, bound, self.timeout.unwrap_or(DEFAULT_TIMEOUT)));
                 }
+                // left behind by an earlier process with our pid, or in use
+                // by someone else; either way it isn't ours to remove
+                Err(ref e) if e.kind() == std::io::ErrorKind::AddrInUse => continue,
                 Err(e) => return Err(e.into()),
             };
         }
     }
 }
 
-struct ClientInternal {
-    buffer: [u8; BUF_SIZE],
-    handle: UnixDatagram,
-    filepath: PathBuf,
-}
+/// The path our end of the socket is bound to, unlinked when dropped
+struct Bound(PathBuf);
 
-fn select(fd: RawFd, duration: Duration) -> Result<bool> {
-    let r = unsafe {
-        let mut raw_fd_set = {
-            let mut raw_fd_set = std::mem::MaybeUninit::<libc::fd_set>::uninit();
-            libc::FD_ZERO(raw_fd_set.as_mut_ptr());
-            raw_fd_set.assume_init()
-        };
-        libc::FD_SET(fd, &mut raw_fd_set);
-        libc::select(
-            fd + 1,
-            &mut raw_fd_set,
-            std::ptr::null_mut(),
-            std::ptr::null_mut(),
-            &mut libc::timeval {
-                tv_sec: duration.as_secs().try_into().unwrap(),
-                tv_usec: duration.subsec_micros().try_into().unwrap(),
-            },
-        )
-    };
-
-    if r >= 0 {
-        Ok(r > 0)
-    } else {
-        Err(Error::Wait)
-    }
-}
-
-impl ClientInternal {
-    /// Check if any messages are available
-    pub fn pending(&mut self) -> Result<bool> {
-        select(self.handle.as_raw_fd(), Duration::from_secs(0))
-    }
-
-    /// Receive a message
-    pub fn recv(&mut self) -> Result<Option<String>> {
-        if self.pending()? {
-            let buf_len = self.handle.recv(&mut self.buffer)?;
-            std::str::from_utf8(&self.buffer[0..buf_len])
-                .map(|s| Some(s.to_owned()))
-                .map_err(std::convert::Into::into)
-        } else {
-            Ok(None)
-        }
-    }
-
-    fn send_request(&mut self, cmd: &str) -> Result<()> {
-        self.handle.send(cmd.as_bytes())?;
-        Ok(())
-    }
-
-    /// Send a command to `wpa_supplicant` / `hostapd`.
-    fn request<F: FnMut(&str)>(&mut self, cmd: &str, mut cb: F) -> Result<String> {
-        self.send_request(cmd)?;
-        loop {
-            select(self.handle.as_raw_fd(), Duration::from_secs(10))?;
-            match self.handle.recv(&mut self.buffer) {
-                Ok(len) => {
-                    let s = std::str::from_utf8(&self.buffer[0..len])?;
-                    if s.starts_with('<') {
-                        cb(s);
-                    } else {
-                        return Ok(s.to_owned());
-                    }
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
-                Err(e) => return Err(e.into()),
-            }
-        }
-    }
-}
-
-impl AsRawFd for ClientInternal {
-    fn as_raw_fd(&self) -> RawFd {
-        self.handle.as_raw_fd()
-    }
-}
-
-impl Drop for ClientInternal {
+impl Drop for Bound {
     fn drop(&mut self) {
-        if let Err(e) = std::fs::remove_file(&self.filepath) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
             eprintln!("wpactrl: Unable to unlink {:?}", e);
         }
     }
 }
 
+/// Control interface messages sent by `wpa_supplicant` / `hostapd` once
+/// attached, eg. `<3>CTRL-EVENT-CONNECTED ...`. Ends when the client is
+/// dropped or the socket fails.
+pub type Events = UnboundedReceiverStream<String>;
+
+/// Where the receiver passes events on to while attached
+type Monitor = Arc<Mutex<Option<mpsc::UnboundedSender<String>>>>;
+
 /// A connection to `wpa_supplicant` / `hostapd`
-pub struct Client(ClientInternal);
+pub struct Client {
+    socket: Arc<UnixDatagram>,
+    replies: mpsc::UnboundedReceiver<Result<String>>,
+    monitor: Monitor,
+    timeout: Duration,
+    // dropped in order: stop receiving, then unlink our path
+    _receiver: Task,
+    _bound: Bound,
+}
 
 impl Client {
     /// Creates a builder for a `wpa_supplicant` / `hostapd` connection
@@ -274,79 +223,122 @@ impl Client {
         ClientBuilder::default()
     }
 
-    /// Register as an event monitor for control interface messages
+    fn new(socket: UnixDatagram, bound: Bound, timeout: Duration) -> Self {
+        let socket = Arc::new(socket);
+        let (replies_tx, replies_rx) = mpsc::unbounded_channel();
+        let monitor = Monitor::default();
+
+        let receiver = future::spawn(receive(socket.clone(), replies_tx, monitor.clone()));
+
+        Client {
+            socket,
+            replies: replies_rx,
+            monitor,
+            timeout,
+            _receiver: receiver,
+            _bound: bound,
+        }
+    }
+
+    /// Send a command to `wpa_supplicant` / `hostapd` and wait for the reply.
+    /// Control interface messages received meanwhile go to [`Events`].
     ///
     /// # Examples
     ///
     /// ```
     /// let mut wpa = wpactrl::Client::builder().open().unwrap();
-    /// let wpa_attached = wpa.attach().unwrap();
+    /// assert_eq!(wpa.request("PING").await.unwrap(), "PONG\n");
     /// ```
     ///
     /// # Errors
     ///
-    /// * [`Error::Attach`] - Unexpected (non-OK) response
     /// * [`Error::Io`] - Low-level I/O error
     /// * [`Error::Utf8ToStr`] - Corrupted message or message with non-UTF8 characters
-    /// * [`Error::Wait`] - Failed to wait on underlying Unix socket
-    pub fn attach(mut self) -> Result<ClientAttached> {
-        // FIXME: None closure would be better
-        if self.0.request("ATTACH", |_: &str| ())? == "OK\n" {
-            Ok(ClientAttached(self.0, VecDeque::new()))
-        } else {
-            Err(Error::Attach)
+    /// * [`Error::Timeout`] - No reply within the timeout
+    pub async fn request(&mut self, cmd: &str) -> Result<String> {
+        // a reply that came after its request timed out would otherwise be
+        // taken for the reply to this one
+        while let Ok(stale) = self.replies.try_recv() {
+            stale?;
         }
-    }
-}
 
-impl AsRawFd for Client {
-    fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
-    }
-}
+        self.socket.send(cmd.as_bytes()).await?;
 
-/// A connection to `wpa_supplicant` / `hostapd` that receives status messages
-pub struct ClientAttached(ClientInternal, VecDeque<String>);
+        match tokio::time::timeout(self.timeout, self.replies.recv()).await {
+            Ok(Some(reply)) => reply,
+            Ok(None) => Err(io::Error::from(io::ErrorKind::BrokenPipe).into()),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
 
-impl ClientAttached {
-    /// Receive the next control interface message.
-    ///
-    /// Note that multiple control interface messages can be pending;
-    /// call this function repeatedly until it returns None to get all of them.
+    /// Register as an event monitor for control interface messages,
+    /// returning them as a stream. Attaching again ends the last stream.
     ///
     /// # Examples
     ///
     /// ```
-    /// let mut wpa = wpactrl::Client::builder().open().unwrap().attach().unwrap();
-    /// assert_eq!(wpa.recv().unwrap(), None);
+    /// let mut wpa = wpactrl::Client::builder().open().unwrap();
+    /// let events = wpa.attach().await.unwrap();
     /// ```
     ///
     /// # Errors
     ///
+    /// * [`Error::Attach`] - Unexpected (non-OK) response
     /// * [`Error::Io`] - Low-level I/O error
     /// * [`Error::Utf8ToStr`] - Corrupted message or message with non-UTF8 characters
-    /// * [`Error::Wait`] - Failed to wait on underlying Unix socket
-    pub fn recv(&mut self) -> Result<Option<String>> {
-        if let Some(s) = self.1.pop_back() {
-            Ok(Some(s))
+    /// * [`Error::Timeout`] - No reply within the timeout
+    pub async fn attach(&mut self) -> Result<Events> {
+        // events can follow the reply straight away, so be ready for them
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        *self.monitor.lock().unwrap() = Some(events_tx);
+
+        if self.request("ATTACH").await? == "OK\n" {
+            Ok(UnboundedReceiverStream::new(events_rx))
         } else {
-            self.0.recv()
+            *self.monitor.lock().unwrap() = None;
+            Err(Error::Attach)
         }
     }
+}
 
-    pub fn send_request(&mut self, cmd: &str) -> Result<()> {
-        self.0.send_request(cmd)
-    }
+/// Whether a message is a control interface message rather than a reply,
+/// as decided by `wpa_ctrl_request` in wpa_supplicant's `wpa_ctrl.c`
+fn is_event(msg: &[u8]) -> bool {
+    msg.starts_with(b"<") || msg.starts_with(b"IFNAME=")
 }
 
-impl AsRawFd for ClientAttached {
-    fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+/// Reads the socket until it fails, passing replies and events on
+async fn receive(
+    socket: Arc<UnixDatagram>,
+    replies: mpsc::UnboundedSender<Result<String>>,
+    monitor: Monitor,
+) {
+    let mut buffer = vec![0; BUF_SIZE];
+
+    loop {
+        let msg = match socket.recv(&mut buffer).await {
+            Ok(len) => &buffer[..len],
+            Err(e) => {
+                let _ = replies.send(Err(e.into()));
+                // ends the events stream
+                monitor.lock().unwrap().take();
+                return;
+            }
+        };
+
+        if is_event(msg) {
+            if let Some(events) = &*monitor.lock().unwrap() {
+                let _ = events.send(String::from_utf8_lossy(msg).into_owned());
+            }
+        } else {
+            let _ = replies.send(str::from_utf8(msg).map(str::to_owned).map_err(Error::from));
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use futures::StreamExt;
     use serial_test::serial;
     use super::*;
 
@@ -354,58 +346,113 @@ mod test {
         Client::builder().open().unwrap()
     }
 
-    #[test]
+    /// A control socket answering each request with `reply`, with a
+    /// control interface message sent ahead of each reply
+    fn server(path: &Path, reply: &'static str) -> Task {
+        let socket = UnixDatagram::bind(path).unwrap();
+
+        future::spawn(async move {
+            let mut buffer = vec![0; BUF_SIZE];
+
+            while let Ok((len, client)) = socket.recv_from(&mut buffer).await {
+                let client = client.as_pathname().unwrap();
+                let cmd = str::from_utf8(&buffer[..len]).unwrap();
+
+                let reply = match cmd {
+                    "ATTACH" => "OK\n",
+                    "SLOW" => continue,
+                    _ => reply,
+                };
+
+                socket.send_to(format!("<3>EVENT {}", cmd).as_bytes(), client).await.unwrap();
+                socket.send_to(reply.as_bytes(), client).await.unwrap();
+            }
+        })
+    }
+
+    #[tokio::test]
     #[serial]
-    fn attach() {
-        wpa_ctrl()
-            .attach()
-            .unwrap()
-            .detach()
-            .unwrap()
-            .attach()
-            .unwrap()
-            .detach()
+    async fn events_and_replies() {
+        let dir = std::env::temp_dir().join(format!("hstatus-wpactrl-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wlan0");
+        let _server = server(&path, "wpa_state=COMPLETED\n");
+
+        let mut wpa = Client::builder()
+            .ctrl_path(&path)
+            .timeout(Duration::from_millis(100))
+            .open()
             .unwrap();
+
+        let mut events = wpa.attach().await.unwrap();
+        assert_eq!(wpa.request("STATUS").await.unwrap(), "wpa_state=COMPLETED\n");
+        assert!(matches!(wpa.request("SLOW").await, Err(Error::Timeout)));
+
+        // the events end with the client
+        drop(wpa);
+        let events = events.by_ref().collect::<Vec<_>>().await;
+        assert_eq!(events, ["<3>EVENT ATTACH", "<3>EVENT STATUS"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    #[test]
+    #[tokio::test]
     #[serial]
-    fn detach() {
-        let wpa = wpa_ctrl().attach().unwrap();
-        wpa.detach().unwrap();
+    async fn clients_side_by_side() {
+        let dir = std::env::temp_dir().join(format!("hstatus-wpactrl-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wlan0");
+        let _server = server(&path, "wpa_state=COMPLETED\n");
+
+        let mut first = Client::builder().ctrl_path(&path).open().unwrap();
+        let mut second = Client::builder().ctrl_path(&path).open().unwrap();
+        assert_ne!(first._bound.0, second._bound.0);
+
+        assert_eq!(first.request("STATUS").await.unwrap(), "wpa_state=COMPLETED\n");
+        assert_eq!(second.request("STATUS").await.unwrap(), "wpa_state=COMPLETED\n");
+
+        // closing one leaves the other's path alone
+        let bound = second._bound.0.clone();
+        drop(first);
+        assert!(bound.exists());
+        assert_eq!(second.request("STATUS").await.unwrap(), "wpa_state=COMPLETED\n");
+
+        drop(second);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    #[ignore = "requires a running wpa_supplicant"]
+    async fn attach() {
+        let mut wpa = wpa_ctrl();
+        wpa.attach().await.unwrap();
     }
 
-    #[test]
+    #[tokio::test]
     #[serial]
-    fn builder() {
+    #[ignore = "requires a running wpa_supplicant"]
+    async fn builder() {
         wpa_ctrl();
     }
 
-    #[test]
+    #[tokio::test]
     #[serial]
-    fn request() {
+    #[ignore = "requires a running wpa_supplicant"]
+    async fn request() {
         let mut wpa = wpa_ctrl();
-        assert_eq!(wpa.request("PING").unwrap(), "PONG\n");
-        let mut wpa_attached = wpa.attach().unwrap();
-        // FIXME: This may not trigger the callback
-        assert_eq!(wpa_attached.request("PING").unwrap(), "PONG\n");
+        assert_eq!(wpa.request("PING").await.unwrap(), "PONG\n");
+        wpa.attach().await.unwrap();
+        assert_eq!(wpa.request("PING").await.unwrap(), "PONG\n");
     }
 
-    #[test]
+    #[tokio::test]
     #[serial]
-    fn recv() {
-        let mut wpa = wpa_ctrl().attach().unwrap();
-        assert_eq!(wpa.recv().unwrap(), None);
-        assert_eq!(wpa.request("SCAN").unwrap(), "OK\n");
-        loop {
-            match wpa.recv().unwrap() {
-                Some(s) => {
-                    assert_eq!(&s[3..], "CTRL-EVENT-SCAN-STARTED ");
-                    break;
-                }
-                None => std::thread::sleep(std::time::Duration::from_millis(10)),
-            }
-        }
-        wpa.detach().unwrap();
+    #[ignore = "requires a running wpa_supplicant"]
+    async fn events() {
+        let mut wpa = wpa_ctrl();
+        let mut events = wpa.attach().await.unwrap();
+        assert_eq!(wpa.request("SCAN").await.unwrap(), "OK\n");
+        assert_eq!(&events.next().await.unwrap()[3..], "CTRL-EVENT-SCAN-STARTED ");
     }
 }